@@ -1,12 +1,20 @@
 #![deny(warnings)]
 #![warn(clippy::all)]
 
-use egui_winit_wgpu::{storage::FileStorage, RunMode};
+use egui_winit_wgpu::{migrations, storage::FileStorage, Autosave, RunMode};
 
 fn main() {
+    // Also forwards `log` records, ours included, to the tracing subscriber.
     wgpu_subscriber::initialize_default_subscriber(None);
     let title = "Egui wgpu demo";
-    let storage = FileStorage::from_path(".egui_demo_wgpu.json".into());
-    let app: egui::DemoApp = egui::app::get_value(&storage, egui::app::APP_KEY).unwrap_or_default();
-    egui_winit_wgpu::run(title, RunMode::Reactive, storage, app);
+    let mut storage = FileStorage::from_app_id("egui_demo_wgpu")
+        .unwrap_or_else(|_| FileStorage::from_path(".egui_demo_wgpu.json"))
+        .with_autosave_interval(std::time::Duration::from_secs(30));
+    if let Some(err) = storage.load_error() {
+        log::warn!("Starting with default settings: {}", err);
+    }
+    let app: egui::DemoApp =
+        migrations::get_value(&mut storage, egui::app::APP_KEY).unwrap_or_default();
+    // `DemoApp::on_exit` only stores the app, so it is safe to call on every autosave.
+    egui_winit_wgpu::run(title, RunMode::Reactive, storage, Autosave(app));
 }
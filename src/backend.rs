@@ -1,8 +1,14 @@
 use futures::executor;
-use std::{iter, time::Instant};
+use std::{
+    collections::HashMap,
+    iter,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use crate::{
-    storage::{FileStorage, WindowSettings},
+    clipboard::Clipboard,
+    storage::{PersistentStorage, WindowSettings},
     *,
 };
 
@@ -15,14 +21,417 @@ pub struct WGpuBackend {
     frame_times: egui::MovementTracker<f32>,
     quit: bool,
     run_mode: RunMode,
+    paint_stats: PaintStats,
+    frame_timings: FrameTimings,
+    pending_windows: Vec<(WindowOptions, Box<dyn WGpuApp>)>,
+    close_window: bool,
+    window_commands: Vec<WindowCommand>,
+    texture_commands: Vec<TextureCommand>,
+    next_user_texture_id: u64,
+    quit_shortcut: Option<Shortcut>,
+    clipboard: Box<dyn Clipboard>,
+    url_handler: Box<UrlHandler>,
+    cursor: Option<Cursor>,
+}
+
+/// Where the time of the previous frame went after egui was done, in seconds.
+///
+/// These are all measured on the CPU. wgpu 0.6 doesn't expose timestamp queries,
+/// so the time the egui render pass itself takes on the GPU can't be measured yet.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameTimings {
+    /// Acquiring the swap chain frame, uploading and recording the egui render pass.
+    pub encode: f32,
+    /// `Queue::submit`
+    pub submit: f32,
+    /// Presenting the swap chain frame. May include waiting for vsync.
+    pub present: f32,
 }
 
 impl WGpuBackend {
     pub fn new(run_mode: RunMode) -> Self {
-        Self { frame_times: egui::MovementTracker::new(1000, 1.0), quit: false, run_mode }
+        Self {
+            frame_times: egui::MovementTracker::new(1000, 1.0),
+            quit: false,
+            run_mode,
+            paint_stats: Default::default(),
+            frame_timings: Default::default(),
+            pending_windows: Vec::new(),
+            close_window: false,
+            window_commands: Vec::new(),
+            texture_commands: Vec::new(),
+            next_user_texture_id: 0,
+            quit_shortcut: Some(Shortcut::LOGO_Q),
+            clipboard: crate::clipboard::system_or_fallback(),
+            url_handler: Box::new(open_in_browser),
+            cursor: None,
+        }
+    }
+
+    /// What the painter did when drawing the previous frame.
+    pub fn paint_stats(&self) -> PaintStats {
+        self.paint_stats
+    }
+
+    /// Encode, submit and present timings of the previous frame.
+    /// Complements `cpu_time`, which only covers the egui part of the frame.
+    pub fn frame_timings(&self) -> FrameTimings {
+        self.frame_timings
+    }
+
+    /// Upload an image for the window the current `ui` call is drawing,
+    /// see [`Painter::register_user_texture`]. The id is only valid in that window.
+    pub fn register_user_texture(
+        &mut self,
+        width: usize,
+        height: usize,
+        rgba: &[u8],
+        options: TextureOptions,
+    ) -> UserTextureId {
+        assert_eq!(rgba.len(), width * height * 4, "expected {}x{} RGBA pixels", width, height);
+
+        let id = UserTextureId(self.next_user_texture_id);
+        self.next_user_texture_id += 1;
+        let rgba = rgba.to_vec();
+        self.texture_commands.push(TextureCommand::Register { id, width, height, rgba, options });
+        id
+    }
+
+    pub fn free_user_texture(&mut self, id: UserTextureId) {
+        self.texture_commands.push(TextureCommand::Free(id));
+    }
+
+    /// Draw a registered image this frame, see [`Painter::draw_user_texture`].
+    /// `clip_rect` is usually `ui.clip_rect()`.
+    pub fn draw_user_texture(
+        &mut self,
+        id: UserTextureId,
+        rect: egui::Rect,
+        clip_rect: egui::Rect,
+    ) {
+        self.texture_commands.push(TextureCommand::Draw { id, rect, clip_rect });
+    }
+
+    /// Open another native window at the end of this frame, drawn by `app`.
+    ///
+    /// The window gets its own egui context and input, but shares the gpu device
+    /// and storage with the main window. Its size, position and egui memory are not persisted.
+    /// A plain egui `app` isn't autosaved, and should store its state under its own keys
+    /// in `App::on_exit` so it doesn't overwrite that of the main window.
+    pub fn open_window(&mut self, title: &str, app: impl WGpuApp + 'static) {
+        self.open_window_with_options(WindowOptions::new(title), app);
+    }
+
+    pub fn open_window_with_options(
+        &mut self,
+        options: WindowOptions,
+        app: impl WGpuApp + 'static,
+    ) {
+        self.pending_windows.push((options, Box::new(app)));
+    }
+
+    /// Close the window the current `ui` call is drawing at the end of this frame.
+    /// Closing the main window quits.
+    pub fn close_window(&mut self) {
+        self.close_window = true;
+    }
+
+    /// Show `cursor` instead of the one egui asks for over the window the current `ui` call
+    /// is drawing. Like egui's cursor this only lasts for one frame, so keep calling it.
+    pub fn set_cursor(&mut self, cursor: Cursor) {
+        self.cursor = Some(cursor);
+    }
+
+    /// What copy, cut and paste use. Defaults to `clipboard::system_or_fallback()`.
+    pub fn clipboard(&mut self) -> &mut dyn Clipboard {
+        self.clipboard.as_mut()
+    }
+
+    pub fn set_clipboard(&mut self, clipboard: impl Clipboard + 'static) {
+        self.clipboard = Box::new(clipboard);
+    }
+
+    /// What to do with links egui wants to open, e.g. `open_in_browser` (the default)
+    /// or `deny_all_urls`. Use a closure to allowlist links or show them in the app instead.
+    pub fn set_url_handler(
+        &mut self,
+        url_handler: impl FnMut(&str, bool) -> Result<(), String> + 'static,
+    ) {
+        self.url_handler = Box::new(url_handler);
+    }
+
+    /// The key combination that asks to close the focused window, see `WGpuApp::on_close_requested`.
+    /// Defaults to `Shortcut::LOGO_Q`, `None` disables it.
+    pub fn set_quit_shortcut(&mut self, shortcut: Option<Shortcut>) {
+        self.quit_shortcut = shortcut;
+    }
+
+    // ------------------------------------------------------------------------
+    // Changes to the window the current `ui` call is drawing, applied at the end of the frame.
+
+    pub fn set_window_title(&mut self, title: &str) {
+        self.window_commands.push(WindowCommand::Title(title.to_owned()));
+    }
+
+    /// Inner size in points.
+    pub fn set_window_size(&mut self, size: egui::Vec2) {
+        self.window_commands.push(WindowCommand::Size(size));
+    }
+
+    /// Outer position in points.
+    pub fn set_window_position(&mut self, pos: egui::Pos2) {
+        self.window_commands.push(WindowCommand::Position(pos));
+    }
+
+    pub fn set_minimized(&mut self, minimized: bool) {
+        self.window_commands.push(WindowCommand::Minimized(minimized));
+    }
+
+    pub fn set_maximized(&mut self, maximized: bool) {
+        self.window_commands.push(WindowCommand::Maximized(maximized));
+    }
+
+    /// Switch between windowed and borderless fullscreen on the current monitor.
+    pub fn toggle_fullscreen(&mut self) {
+        self.window_commands.push(WindowCommand::ToggleFullscreen);
+    }
+
+    pub fn set_always_on_top(&mut self, always_on_top: bool) {
+        self.window_commands.push(WindowCommand::AlwaysOnTop(always_on_top));
+    }
+
+    /// Hide the cursor over the window until this is called with `true`.
+    /// Unlike `set_cursor(Cursor::Hidden)`, this lasts until changed and wins over `set_cursor`.
+    pub fn set_cursor_visible(&mut self, visible: bool) {
+        self.window_commands.push(WindowCommand::CursorVisible(visible));
+    }
+
+    /// Keep the cursor inside the window. Not supported on every platform.
+    pub fn set_cursor_grab(&mut self, grab: bool) {
+        self.window_commands.push(WindowCommand::CursorGrab(grab));
+    }
+
+    /// `rgba` is `width * height` unmultiplied pixels.
+    pub fn set_window_icon(&mut self, rgba: Vec<u8>, width: u32, height: u32) {
+        self.window_commands.push(WindowCommand::Icon { rgba, width, height });
+    }
+
+    /// Bounce the dock icon if the app isn't focused. Only supported on macOS for now.
+    pub fn request_attention(&mut self) {
+        self.window_commands.push(WindowCommand::RequestAttention);
+    }
+
+    pub fn set_decorations(&mut self, decorations: bool) {
+        self.window_commands.push(WindowCommand::Decorations(decorations));
+    }
+
+    /// What the window is cleared to before egui paints, see [`WindowOptions::with_clear_color`].
+    pub fn set_clear_color(&mut self, rgba: [f32; 4]) {
+        self.window_commands.push(WindowCommand::ClearColor(rgba));
+    }
+
+    // ------------------------------------------------------------------------
+    // Custom window chrome for windows without decorations.
+    // Minimize, maximize and close buttons can use `set_minimized`, `set_maximized` and `close_window`.
+    // For rounded corners, make the window transparent and paint the background yourself.
+
+    /// Move the window with the mouse until the button is released.
+    /// Call this when a custom title bar is pressed or starts being dragged.
+    pub fn drag_window(&mut self) {
+        self.window_commands.push(WindowCommand::Drag(None));
+    }
+
+    /// Resize the window from `edge` with the mouse until the button is released.
+    /// Call this when a custom resize handle is pressed or starts being dragged.
+    pub fn drag_resize_window(&mut self, edge: ResizeEdge) {
+        self.window_commands.push(WindowCommand::Drag(Some(edge)));
     }
 }
 
+/// The edge or corner of a window to resize from, see [`WGpuBackend::drag_resize_window`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResizeEdge {
+    North,
+    South,
+    East,
+    West,
+    NorthEast,
+    NorthWest,
+    SouthEast,
+    SouthWest,
+}
+
+impl ResizeEdge {
+    fn moves_west(self) -> bool {
+        matches!(self, ResizeEdge::West | ResizeEdge::NorthWest | ResizeEdge::SouthWest)
+    }
+
+    fn moves_east(self) -> bool {
+        matches!(self, ResizeEdge::East | ResizeEdge::NorthEast | ResizeEdge::SouthEast)
+    }
+
+    fn moves_north(self) -> bool {
+        matches!(self, ResizeEdge::North | ResizeEdge::NorthEast | ResizeEdge::NorthWest)
+    }
+
+    fn moves_south(self) -> bool {
+        matches!(self, ResizeEdge::South | ResizeEdge::SouthEast | ResizeEdge::SouthWest)
+    }
+}
+
+/// How a native window is created, see [`run_with_options`].
+#[derive(Clone, Debug)]
+pub struct WindowOptions {
+    pub title: String,
+    /// `false` for windows that draw their own title bar, see [`WGpuBackend::drag_window`].
+    pub decorations: bool,
+    pub resizable: bool,
+    /// Let the desktop show through wherever the window isn't opaque.
+    ///
+    /// wgpu 0.6 doesn't let us choose how the swap chain is composited, so this works where the
+    /// platform composites transparent windows by itself (e.g. Metal, DX12 and most X11 and Wayland
+    /// compositors with GL), but the window stays opaque with backends that create opaque swap chains.
+    pub transparent: bool,
+    /// Premultiplied RGBA in gamma space, like egui's colors.
+    pub clear_color: [f32; 4],
+}
+
+impl WindowOptions {
+    pub fn new(title: &str) -> Self {
+        Self {
+            title: title.to_owned(),
+            decorations: true,
+            resizable: true,
+            transparent: false,
+            clear_color: [0.0, 0.0, 0.0, 0.0],
+        }
+    }
+
+    pub fn with_decorations(mut self, decorations: bool) -> Self {
+        self.decorations = decorations;
+        self
+    }
+
+    pub fn with_resizable(mut self, resizable: bool) -> Self {
+        self.resizable = resizable;
+        self
+    }
+
+    /// Only what egui paints is visible as long as the clear color stays fully transparent,
+    /// which it is by default.
+    pub fn with_transparent(mut self, transparent: bool) -> Self {
+        self.transparent = transparent;
+        self
+    }
+
+    /// `rgba` is premultiplied, in gamma space like egui's colors.
+    pub fn with_clear_color(mut self, rgba: [f32; 4]) -> Self {
+        self.clear_color = rgba;
+        self
+    }
+
+    fn window_builder(&self) -> winit::window::WindowBuilder {
+        winit::window::WindowBuilder::new()
+            .with_decorations(self.decorations)
+            .with_resizable(self.resizable)
+            .with_title(&self.title)
+            .with_transparent(self.transparent)
+    }
+}
+
+/// See the user texture methods of [`WGpuBackend`].
+enum TextureCommand {
+    Register {
+        id: UserTextureId,
+        width: usize,
+        height: usize,
+        rgba: Vec<u8>,
+        options: TextureOptions,
+    },
+    Free(UserTextureId),
+    Draw {
+        id: UserTextureId,
+        rect: egui::Rect,
+        clip_rect: egui::Rect,
+    },
+}
+
+/// See the setters on [`WGpuBackend`].
+enum WindowCommand {
+    Title(String),
+    Size(egui::Vec2),
+    Position(egui::Pos2),
+    Minimized(bool),
+    Maximized(bool),
+    ToggleFullscreen,
+    AlwaysOnTop(bool),
+    CursorVisible(bool),
+    CursorGrab(bool),
+    Icon {
+        rgba: Vec<u8>,
+        width: u32,
+        height: u32,
+    },
+    RequestAttention,
+    Decorations(bool),
+    ClearColor([f32; 4]),
+    /// Move (`None`) or resize the window with the mouse.
+    Drag(Option<ResizeEdge>),
+}
+
+impl WindowCommand {
+    fn apply(self, window: &winit::window::Window) {
+        use winit::dpi::{LogicalPosition, LogicalSize};
+        match self {
+            WindowCommand::Title(title) => window.set_title(&title),
+            WindowCommand::Size(size) => window.set_inner_size(LogicalSize::new(size.x, size.y)),
+            WindowCommand::Position(pos) => {
+                window.set_outer_position(LogicalPosition::new(pos.x, pos.y))
+            }
+            WindowCommand::Minimized(minimized) => window.set_minimized(minimized),
+            WindowCommand::Maximized(maximized) => window.set_maximized(maximized),
+            WindowCommand::ToggleFullscreen => {
+                if window.fullscreen().is_some() {
+                    window.set_fullscreen(None);
+                } else {
+                    let monitor = window.current_monitor();
+                    window.set_fullscreen(Some(winit::window::Fullscreen::Borderless(monitor)));
+                }
+            }
+            WindowCommand::AlwaysOnTop(always_on_top) => window.set_always_on_top(always_on_top),
+            WindowCommand::CursorGrab(grab) => {
+                if let Err(err) = window.set_cursor_grab(grab) {
+                    log::warn!("Failed to grab the cursor: {}", err);
+                }
+            }
+            WindowCommand::Icon { rgba, width, height } => {
+                match winit::window::Icon::from_rgba(rgba, width, height) {
+                    Ok(icon) => window.set_window_icon(Some(icon)),
+                    Err(err) => log::warn!("Invalid window icon: {}", err),
+                }
+            }
+            WindowCommand::RequestAttention => request_attention(window),
+            WindowCommand::Decorations(decorations) => window.set_decorations(decorations),
+            // These change the viewport, not the window, see `Viewport::apply`.
+            WindowCommand::ClearColor(_)
+            | WindowCommand::CursorVisible(_)
+            | WindowCommand::Drag(_) => {}
+        }
+    }
+}
+
+/// winit 0.23 can only do this on macOS.
+#[cfg(target_os = "macos")]
+fn request_attention(window: &winit::window::Window) {
+    use winit::platform::macos::{RequestUserAttentionType, WindowExtMacOS};
+    window.request_user_attention(RequestUserAttentionType::Informational);
+}
+
+#[cfg(not(target_os = "macos"))]
+fn request_attention(_window: &winit::window::Window) {
+    log::debug!("Requesting attention is only supported on macOS");
+}
+
 impl Backend for WGpuBackend {
     fn run_mode(&self) -> RunMode {
         self.run_mode
@@ -45,21 +454,455 @@ impl Backend for WGpuBackend {
     }
 }
 
+/// An app that can use the wgpu specific parts of [`WGpuBackend`].
+///
+/// Every `egui::app::App` is a `WGpuApp`, so plain egui apps can be passed to [`run`] as well.
+pub trait WGpuApp {
+    fn ui(&mut self, ui: &mut egui::Ui, backend: &mut WGpuBackend);
+
+    /// Called on every autosave (see `PersistentStorage::next_autosave`) and before `on_exit`.
+    /// Store any app state that should survive a crash here.
+    fn save(&mut self, _storage: &mut dyn Storage) {}
+
+    /// Called when values in the storage were changed by someone else,
+    /// e.g. the settings file was edited while the app was running.
+    fn on_storage_changed(&mut self, _storage: &mut dyn Storage, _keys: &[String]) {}
+
+    /// Called when the user asks to close the window this app draws,
+    /// with the window's close button or the quit shortcut.
+    ///
+    /// Return `false` to keep the window open, e.g. to ask about unsaved changes first.
+    /// Call `WGpuBackend::close_window` from `ui` later to close it after all.
+    fn on_close_requested(&mut self) -> bool {
+        true
+    }
+
+    /// Called before `ui` with the problems since the last frame that the user may want to
+    /// know about, e.g. a link that couldn't be opened. They are logged either way.
+    fn on_notification(&mut self, _notification: &Notification) {}
+
+    /// Called once before shutdown, or when the app's window is closed,
+    /// right after a final `save`. See `egui::app::App::on_exit`.
+    fn on_exit(&mut self, _storage: &mut dyn Storage) {}
+}
+
+/// Plain egui apps are only saved on exit, as `App::on_exit` promises to be called once.
+/// Wrap them in [`Autosave`] to save them on every autosave too.
+impl<T: App> WGpuApp for T {
+    fn ui(&mut self, ui: &mut egui::Ui, backend: &mut WGpuBackend) {
+        App::ui(self, ui, backend)
+    }
+
+    fn on_exit(&mut self, storage: &mut dyn Storage) {
+        App::on_exit(self, storage)
+    }
+}
+
+/// An egui app whose `App::on_exit` only stores its state, so it can be called on every autosave.
+///
+/// Only wrap the app of the main window: apps in other windows would store the same keys.
+pub struct Autosave<T>(pub T);
+
+impl<T: App> WGpuApp for Autosave<T> {
+    fn ui(&mut self, ui: &mut egui::Ui, backend: &mut WGpuBackend) {
+        self.0.ui(ui, backend)
+    }
+
+    fn save(&mut self, storage: &mut dyn Storage) {
+        self.0.on_exit(storage)
+    }
+
+    // `save` has already called `App::on_exit` right before this.
+    fn on_exit(&mut self, _storage: &mut dyn Storage) {}
+}
+
+/// Put everything that should be restored on the next launch into `storage`.
+fn save_state(
+    storage: &mut dyn Storage,
+    window: &winit::window::Window,
+    window_settings: &mut WindowSettings,
+    ctx: &egui::Context,
+    app: &mut impl WGpuApp,
+) {
+    window_settings.update_from_display(window);
+    egui::app::set_value(storage, WINDOW_KEY, &*window_settings);
+    egui::app::set_value(storage, EGUI_MEMORY_KEY, &*ctx.memory());
+    app.save(storage);
+}
+
+/// A native window and everything needed to run egui in it.
+struct Viewport {
+    swap_chain: wgpu::SwapChain,
+    sc_desc: wgpu::SwapChainDescriptor,
+    surface: wgpu::Surface,
+    ctx: Arc<egui::Context>,
+    painter: Painter,
+    raw_input: egui::RawInput,
+    modifier_state: winit::event::ModifiersState,
+    /// Frames in a row we failed to get because the swap chain was lost,
+    /// since the device was last recreated.
+    lost_frames: u32,
+    /// The last position of the mouse inside the window.
+    cursor_pos: Option<winit::dpi::PhysicalPosition<f64>>,
+    drag: Option<WindowDrag>,
+    clear_color: [f32; 4],
+    /// For the app, see `WGpuApp::on_notification`.
+    notifications: Vec<Notification>,
+    /// The cursor last shown over the window, see `update_cursor`.
+    cursor: Option<Cursor>,
+    /// Set with `WGpuBackend::set_cursor_visible`.
+    cursor_hidden: bool,
+    // Declared last so it is dropped after its surface.
+    window: winit::window::Window,
+}
+
+impl Viewport {
+    fn new(
+        window: winit::window::Window,
+        surface: wgpu::Surface,
+        device: &wgpu::Device,
+        painter: Painter,
+        clear_color: [f32; 4],
+    ) -> Self {
+        let size = window.inner_size();
+        let sc_desc = wgpu::SwapChainDescriptor {
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+            format: SWAP_CHAIN_FORMAT,
+            width: size.width,
+            height: size.height,
+            present_mode: wgpu::PresentMode::Mailbox,
+        };
+        let swap_chain = device.create_swap_chain(&surface, &sc_desc);
+        let raw_input = make_raw_input(&window);
+        Self {
+            swap_chain,
+            sc_desc,
+            surface,
+            ctx: egui::Context::new(),
+            painter,
+            raw_input,
+            modifier_state: winit::event::ModifiersState::empty(),
+            lost_frames: 0,
+            cursor_pos: None,
+            drag: None,
+            clear_color,
+            notifications: Vec::new(),
+            cursor: None,
+            cursor_hidden: false,
+            window,
+        }
+    }
+
+    fn apply(&mut self, command: WindowCommand) {
+        match command {
+            WindowCommand::Drag(edge) => {
+                self.drag = self.screen_cursor_pos().and_then(|start_cursor| {
+                    Some(WindowDrag {
+                        edge,
+                        start_cursor,
+                        start_pos: self.window.outer_position().ok()?,
+                        start_size: self.window.inner_size(),
+                    })
+                });
+            }
+            WindowCommand::ClearColor(rgba) => self.clear_color = rgba,
+            // Shown by `run_ui`, along with any other cursor.
+            WindowCommand::CursorVisible(visible) => {
+                self.cursor_hidden = !visible;
+                self.window.request_redraw();
+            }
+            command => command.apply(&self.window),
+        }
+    }
+
+    /// The mouse position in physical desktop coordinates.
+    fn screen_cursor_pos(&self) -> Option<winit::dpi::PhysicalPosition<f64>> {
+        let window_pos = self.window.inner_position().ok()?;
+        let cursor_pos = self.cursor_pos?;
+        Some(winit::dpi::PhysicalPosition::new(
+            window_pos.x as f64 + cursor_pos.x,
+            window_pos.y as f64 + cursor_pos.y,
+        ))
+    }
+
+    fn update_drag(&self) {
+        let (drag, cursor) = match (&self.drag, self.screen_cursor_pos()) {
+            (Some(drag), Some(cursor)) => (drag, cursor),
+            _ => return,
+        };
+        let dx = (cursor.x - drag.start_cursor.x) as i32;
+        let dy = (cursor.y - drag.start_cursor.y) as i32;
+        let edge = match drag.edge {
+            Some(edge) => edge,
+            None => {
+                let pos = drag.start_pos;
+                self.window
+                    .set_outer_position(winit::dpi::PhysicalPosition::new(pos.x + dx, pos.y + dy));
+                return;
+            }
+        };
+
+        let (mut x, mut y) = (drag.start_pos.x, drag.start_pos.y);
+        let (mut width, mut height) = (drag.start_size.width as i32, drag.start_size.height as i32);
+        if edge.moves_east() {
+            width += dx;
+        }
+        if edge.moves_west() {
+            width -= dx;
+            x += dx;
+        }
+        if edge.moves_south() {
+            height += dy;
+        }
+        if edge.moves_north() {
+            height -= dy;
+            y += dy;
+        }
+        if width < MIN_DRAG_SIZE || height < MIN_DRAG_SIZE {
+            return;
+        }
+        self.window.set_inner_size(winit::dpi::PhysicalSize::new(width as u32, height as u32));
+        if edge.moves_west() || edge.moves_north() {
+            self.window.set_outer_position(winit::dpi::PhysicalPosition::new(x, y));
+        }
+    }
+
+    fn apply_texture_commands(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        commands: impl Iterator<Item = TextureCommand>,
+    ) {
+        for command in commands {
+            match command {
+                TextureCommand::Register { id, width, height, rgba, options } => self
+                    .painter
+                    .upload_user_texture(device, queue, id, (width, height), rgba, options),
+                TextureCommand::Free(id) => self.painter.free_user_texture(id),
+                TextureCommand::Draw { id, rect, clip_rect } => {
+                    self.painter.draw_user_texture(id, rect, clip_rect)
+                }
+            }
+        }
+    }
+
+    fn recreate_swap_chain(&mut self, device: &wgpu::Device) {
+        self.swap_chain = device.create_swap_chain(&self.surface, &self.sc_desc);
+    }
+
+    /// Returns `true` if the user asked to close the window.
+    fn on_event(
+        &mut self,
+        device: &wgpu::Device,
+        event: winit::event::WindowEvent,
+        clipboard: &mut dyn Clipboard,
+        quit_shortcut: Option<Shortcut>,
+    ) -> bool {
+        match &event {
+            winit::event::WindowEvent::Resized(size) => {
+                self.sc_desc.width = size.width;
+                self.sc_desc.height = size.height;
+                self.recreate_swap_chain(device);
+            }
+            winit::event::WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_pos = Some(*position);
+                self.update_drag();
+            }
+            // A release in another window never reaches this one.
+            winit::event::WindowEvent::MouseInput {
+                state: winit::event::ElementState::Released,
+                ..
+            }
+            | winit::event::WindowEvent::Focused(false) => self.drag = None,
+            _ => {}
+        }
+        let close_requested = input_to_egui(
+            event,
+            clipboard,
+            &mut self.raw_input,
+            &mut self.modifier_state,
+            quit_shortcut,
+            &mut self.notifications,
+        );
+        self.window.request_redraw(); // TODO: maybe only on some events?
+        close_requested
+    }
+
+    fn run_ui(
+        &mut self,
+        app: &mut dyn WGpuApp,
+        backend: &mut WGpuBackend,
+        time: f64,
+    ) -> (egui::Output, egui::paint::PaintJobs) {
+        self.raw_input.time = time;
+        self.raw_input.seconds_since_midnight = Some(local_time_of_day());
+
+        for notification in self.notifications.drain(..) {
+            app.on_notification(&notification);
+        }
+
+        backend.cursor = None;
+        let mut ui = self.ctx.begin_frame(self.raw_input.take());
+        app.ui(&mut ui, backend);
+        let (output, paint_jobs) = self.ctx.end_frame();
+
+        let cursor = if self.cursor_hidden {
+            Cursor::Hidden
+        } else {
+            backend.cursor.unwrap_or(Cursor::Icon(translate_cursor(output.cursor_icon)))
+        };
+        update_cursor(&self.window, cursor, &mut self.cursor);
+        (output, paint_jobs)
+    }
+
+    fn paint(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        paint_jobs: egui::paint::PaintJobs,
+    ) -> Result<(PaintStats, FrameTimings), wgpu::SwapChainError> {
+        let encode_start = Instant::now();
+        let frame = match self.swap_chain.get_current_frame() {
+            Ok(frame) => {
+                self.lost_frames = 0;
+                frame
+            }
+            Err(e) => {
+                log::warn!("Dropped frame with error: {}", e);
+                match e {
+                    wgpu::SwapChainError::Timeout => {}
+                    wgpu::SwapChainError::Outdated => self.recreate_swap_chain(device),
+                    wgpu::SwapChainError::Lost | wgpu::SwapChainError::OutOfMemory => {
+                        // `run` recreates the device if a fresh swap chain doesn't help.
+                        self.lost_frames += 1;
+                        self.recreate_swap_chain(device);
+                    }
+                }
+                self.window.request_redraw();
+                return Err(e);
+            }
+        };
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some(concat!(file!(), "::encoder")),
+        });
+        let paint_stats = {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                    attachment: &frame.output.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color {
+                            r: self.clear_color[0] as f64,
+                            g: self.clear_color[1] as f64,
+                            b: self.clear_color[2] as f64,
+                            a: self.clear_color[3] as f64,
+                        }),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+            self.painter.paint_jobs(
+                paint_jobs,
+                self.window.inner_size().cast(),
+                self.window.scale_factor(),
+                device,
+                queue,
+                &mut rpass,
+                self.ctx.texture(),
+            )
+        };
+        let command_buffer = encoder.finish();
+
+        let submit_start = Instant::now();
+        queue.submit(iter::once(command_buffer));
+
+        // The frame is presented when it is dropped.
+        let present_start = Instant::now();
+        drop(frame);
+
+        let frame_timings = FrameTimings {
+            encode: (submit_start - encode_start).as_secs_f32(),
+            submit: (present_start - submit_start).as_secs_f32(),
+            present: present_start.elapsed().as_secs_f32(),
+        };
+        Ok((paint_stats, frame_timings))
+    }
+}
+
+/// A move or resize of the window that follows the mouse, see [`WGpuBackend::drag_window`].
+///
+/// winit 0.23 can't hand this over to the window manager, so the window is moved by hand.
+/// This doesn't work on Wayland, where windows can't position themselves.
+struct WindowDrag {
+    /// `None` when moving the window.
+    edge: Option<ResizeEdge>,
+    start_cursor: winit::dpi::PhysicalPosition<f64>,
+    start_pos: winit::dpi::PhysicalPosition<i32>,
+    start_size: winit::dpi::PhysicalSize<u32>,
+}
+
+/// Resizing by dragging stops at this many physical pixels.
+const MIN_DRAG_SIZE: i32 = 32;
+
+const SWAP_CHAIN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Bgra8Unorm;
+
+/// Windows opened with [`WGpuBackend::open_window`], each with the app drawing it.
+type Windows = HashMap<winit::window::WindowId, (Viewport, Box<dyn WGpuApp>)>;
+
+fn close_window(windows: &mut Windows, id: winit::window::WindowId, storage: &mut dyn Storage) {
+    if let Some((_, mut app)) = windows.remove(&id) {
+        app.save(storage);
+        app.on_exit(storage);
+    }
+}
+
+/// How long to wait before trying to recreate a lost device again, doubling on every failure.
+const MIN_DEVICE_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_DEVICE_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Returns `None` if there is no suitable adapter, e.g. while a lost gpu is being reset.
+fn request_device(
+    instance: &wgpu::Instance,
+    surface: &wgpu::Surface,
+) -> Option<(wgpu::Device, wgpu::Queue)> {
+    let adapter = executor::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::Default,
+        compatible_surface: Some(surface),
+    }))?;
+
+    executor::block_on(adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            features: wgpu::Features::empty(),
+            limits: wgpu::Limits::default(),
+            shader_validation: false,
+        },
+        None,
+    ))
+    .ok()
+}
+
 /// Run an egui app
 pub fn run(
     title: &str,
     run_mode: RunMode,
-    mut storage: FileStorage,
-    mut app: impl App + 'static,
+    storage: impl PersistentStorage + 'static,
+    app: impl WGpuApp + 'static,
+) -> ! {
+    run_with_options(WindowOptions::new(title), run_mode, storage, app)
+}
+
+/// Run an egui app in a main window created with `options`.
+pub fn run_with_options(
+    options: WindowOptions,
+    run_mode: RunMode,
+    mut storage: impl PersistentStorage + 'static,
+    mut app: impl WGpuApp + 'static,
 ) -> ! {
     let event_loop = winit::event_loop::EventLoop::new();
-    let mut window = winit::window::WindowBuilder::new()
-        .with_decorations(true)
-        .with_resizable(true)
-        .with_title(title)
-        .with_transparent(false);
+    let mut window = options.window_builder();
 
-    let window_settings: Option<WindowSettings> = egui::app::get_value(&storage, WINDOW_KEY);
+    let window_settings: Option<WindowSettings> = migrations::get_value(&mut storage, WINDOW_KEY);
     if let Some(window_settings) = &window_settings {
         window = window_settings.initialize_size(window);
     }
@@ -68,141 +911,234 @@ pub fn run(
     let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
     let surface = unsafe { instance.create_surface(&window) };
 
-    let adapter = executor::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
-        power_preference: wgpu::PowerPreference::Default,
-        compatible_surface: Some(&surface),
-    }))
-    .unwrap();
+    let (mut device, mut queue) = request_device(&instance, &surface).unwrap();
 
-    let (device, queue) = executor::block_on(adapter.request_device(
-        &wgpu::DeviceDescriptor {
-            features: wgpu::Features::empty(),
-            limits: wgpu::Limits::default(),
-            shader_validation: false,
-        },
-        None,
-    ))
-    .unwrap();
-
-    let size = window.inner_size();
-    let mut sc_desc = wgpu::SwapChainDescriptor {
-        usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
-        format: wgpu::TextureFormat::Bgra8Unorm,
-        width: size.width as u32,
-        height: size.height as u32,
-        present_mode: wgpu::PresentMode::Mailbox,
-    };
-    let mut swap_chain = device.create_swap_chain(&surface, &sc_desc);
+    let painter = Painter::new(&device, SWAP_CHAIN_FORMAT);
+    let mut main = Viewport::new(window, surface, &device, painter, options.clear_color);
 
     if let Some(window_settings) = &window_settings {
-        window_settings.restore_positions(&window);
+        window_settings.restore_positions(&main.window);
     }
+    let mut window_settings =
+        window_settings.unwrap_or_else(|| WindowSettings::from_display(&main.window));
 
-    let mut ctx = egui::Context::new();
-    *ctx.memory() = egui::app::get_value(&storage, EGUI_MEMORY_KEY).unwrap_or_default();
+    *main.ctx.memory() = migrations::get_value(&mut storage, EGUI_MEMORY_KEY).unwrap_or_default();
 
-    let mut painter = Painter::new(&device, sc_desc.format);
-    let mut raw_input = make_raw_input(&window);
+    let mut windows = Windows::new();
+    // When to try recreating the device next, and how long to wait after that if it fails again.
+    let mut next_device_attempt = Instant::now();
+    let mut device_retry_delay = MIN_DEVICE_RETRY_DELAY;
 
     // used to keep track of time for animations
     let start_time = Instant::now();
     let mut runner = WGpuBackend::new(run_mode);
-    let mut clipboard = init_clipboard();
-    let mut modifier_state = winit::event::ModifiersState::empty();
 
-    event_loop.run(move |event, _, control_flow| {
+    event_loop.run(move |event, event_loop, control_flow| {
         *control_flow = winit::event_loop::ControlFlow::Wait;
 
         match event {
             winit::event::Event::RedrawEventsCleared => {
+                let changed_keys = storage.reload_external_changes();
+                if !changed_keys.is_empty() {
+                    if changed_keys.iter().any(|key| key == EGUI_MEMORY_KEY) {
+                        if let Some(memory) = migrations::get_value(&mut storage, EGUI_MEMORY_KEY) {
+                            *main.ctx.memory() = memory;
+                        }
+                    }
+                    app.on_storage_changed(&mut storage, &changed_keys);
+                    for (_, window_app) in windows.values_mut() {
+                        window_app.on_storage_changed(&mut storage, &changed_keys);
+                    }
+                }
+
                 let egui_start = Instant::now();
-                raw_input.time = start_time.elapsed().as_nanos() as f64 * 1e-9;
-                raw_input.seconds_since_midnight = Some(local_time_of_day());
+                let time = start_time.elapsed().as_nanos() as f64 * 1e-9;
 
-                let mut ui = ctx.begin_frame(raw_input.take());
-                app.ui(&mut ui, &mut runner);
-                let (output, paint_jobs) = ctx.end_frame();
+                runner.close_window = false;
+                let (output, paint_jobs) = main.run_ui(&mut app, &mut runner, time);
+                if runner.close_window {
+                    runner.quit = true;
+                }
 
                 let frame_time = (Instant::now() - egui_start).as_secs_f64() as f32;
-                runner.frame_times.add(raw_input.time, frame_time);
+                runner.frame_times.add(time, frame_time);
 
-                let frame = match swap_chain.get_current_frame() {
-                    Ok(frame) => frame,
-                    Err(e) => {
-                        eprintln!("Dropped frame with error: {}", e);
-                        return;
+                main.apply_texture_commands(&device, &queue, runner.texture_commands.drain(..));
+                // Stats and timings are only reported for the main window.
+                if let Ok((paint_stats, frame_timings)) = main.paint(&device, &queue, paint_jobs) {
+                    runner.paint_stats = paint_stats;
+                    runner.frame_timings = frame_timings;
+                }
+                let mut needs_repaint = output.needs_repaint;
+                handle_output(
+                    output,
+                    runner.clipboard.as_mut(),
+                    runner.url_handler.as_mut(),
+                    &mut main.notifications,
+                );
+                // Show new notifications right away.
+                needs_repaint |= !main.notifications.is_empty();
+                for command in runner.window_commands.drain(..) {
+                    main.apply(command);
+                }
+
+                let mut closed = Vec::new();
+                for (&id, (viewport, window_app)) in &mut windows {
+                    runner.close_window = false;
+                    let (output, paint_jobs) =
+                        viewport.run_ui(window_app.as_mut(), &mut runner, time);
+                    if runner.close_window {
+                        closed.push(id);
                     }
-                };
-                let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                    label: Some(concat!(file!(), "::encoder")),
-                });
-                {
-                    let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                        color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                            attachment: &frame.output.view,
-                            resolve_target: None,
-                            ops: wgpu::Operations {
-                                load: wgpu::LoadOp::Clear(wgpu::Color {
-                                    r: 0.0,
-                                    g: 0.0,
-                                    b: 0.0,
-                                    a: 0.0,
-                                }),
-                                store: true,
-                            },
-                        }],
-                        depth_stencil_attachment: None,
-                    });
-                    painter.paint_jobs(
-                        paint_jobs,
-                        window.inner_size().cast(),
-                        window.scale_factor(),
+                    viewport.apply_texture_commands(
                         &device,
                         &queue,
-                        &mut rpass,
-                        ctx.texture(),
+                        runner.texture_commands.drain(..),
                     );
+                    let _ = viewport.paint(&device, &queue, paint_jobs);
+                    needs_repaint |= output.needs_repaint;
+                    handle_output(
+                        output,
+                        runner.clipboard.as_mut(),
+                        runner.url_handler.as_mut(),
+                        &mut viewport.notifications,
+                    );
+                    needs_repaint |= !viewport.notifications.is_empty();
+                    for command in runner.window_commands.drain(..) {
+                        viewport.apply(command);
+                    }
+                }
+                runner.close_window = false;
+                for id in closed {
+                    close_window(&mut windows, id, &mut storage);
+                }
+
+                let lost = main.lost_frames > 1
+                    || windows.values().any(|(viewport, _)| viewport.lost_frames > 1);
+                if !lost {
+                    device_retry_delay = MIN_DEVICE_RETRY_DELAY;
+                } else if next_device_attempt <= Instant::now() {
+                    // A fresh swap chain didn't help, so assume the device itself is gone.
+                    // egui and app state live on the CPU and are kept.
+                    //
+                    // This only covers what wgpu 0.6 reports through the swap chain: it can't
+                    // detect a lost device, and panics when submitting to or creating anything
+                    // on one, before we get here.
+                    next_device_attempt = Instant::now() + device_retry_delay;
+                    device_retry_delay = (device_retry_delay * 2).min(MAX_DEVICE_RETRY_DELAY);
+                    match request_device(&instance, &main.surface) {
+                        Some((new_device, new_queue)) => {
+                            device = new_device;
+                            queue = new_queue;
+                            main.painter.recreate(&device, &queue);
+                            main.recreate_swap_chain(&device);
+                            main.lost_frames = 0;
+                            for (viewport, _) in windows.values_mut() {
+                                viewport.painter.recreate_sharing(&main.painter, &device, &queue);
+                                viewport.recreate_swap_chain(&device);
+                                viewport.lost_frames = 0;
+                            }
+                        }
+                        None => log::error!("Failed to recreate the wgpu device"),
+                    }
+                }
+
+                for (options, window_app) in runner.pending_windows.drain(..) {
+                    match options.window_builder().build(event_loop) {
+                        Ok(window) => {
+                            let surface = unsafe { instance.create_surface(&window) };
+                            let painter = main.painter.new_sharing(&device);
+                            let viewport = Viewport::new(
+                                window,
+                                surface,
+                                &device,
+                                painter,
+                                options.clear_color,
+                            );
+                            viewport.window.request_redraw();
+                            windows.insert(viewport.window.id(), (viewport, window_app));
+                        }
+                        Err(err) => {
+                            log::error!("Failed to open window {:?}: {}", options.title, err)
+                        }
+                    }
                 }
-                queue.submit(iter::once(encoder.finish()));
 
                 *control_flow = if runner.quit {
                     winit::event_loop::ControlFlow::Exit
                 } else if runner.run_mode() == RunMode::Continuous {
-                    window.request_redraw();
+                    main.window.request_redraw();
                     winit::event_loop::ControlFlow::Poll
                 } else {
-                    if output.needs_repaint {
-                        window.request_redraw();
+                    if needs_repaint {
+                        main.window.request_redraw();
+                    }
+                    let wake_up = match (storage.next_autosave(), storage.next_reload_check()) {
+                        (Some(a), Some(b)) => Some(a.min(b)),
+                        (a, b) => a.or(b),
+                    };
+                    match wake_up {
+                        Some(wake_up) => winit::event_loop::ControlFlow::WaitUntil(wake_up),
+                        None => winit::event_loop::ControlFlow::Wait,
                     }
-                    winit::event_loop::ControlFlow::Wait
                 };
 
-                handle_output(output, &window, clipboard.as_mut());
+                if matches!(storage.next_autosave(), Some(next) if next <= Instant::now()) {
+                    save_state(
+                        &mut storage,
+                        &main.window,
+                        &mut window_settings,
+                        &main.ctx,
+                        &mut app,
+                    );
+                    for (_, window_app) in windows.values_mut() {
+                        window_app.save(&mut storage);
+                    }
+                    storage.save_in_background();
+                }
             }
-            winit::event::Event::WindowEvent { event, .. } => {
-                if let winit::event::WindowEvent::Resized(size) = event {
-                    sc_desc.width = size.width;
-                    sc_desc.height = size.height;
-                    swap_chain = device.create_swap_chain(&surface, &sc_desc);
+            winit::event::Event::WindowEvent { window_id, event } => {
+                let destroyed = matches!(event, winit::event::WindowEvent::Destroyed);
+                if window_id == main.window.id() {
+                    let moved = matches!(
+                        event,
+                        winit::event::WindowEvent::Resized(_) | winit::event::WindowEvent::Moved(_)
+                    );
+                    let close_requested = main.on_event(
+                        &device,
+                        event,
+                        runner.clipboard.as_mut(),
+                        runner.quit_shortcut,
+                    );
+                    if moved {
+                        // Keep track of the normal geometry before the window is maximized.
+                        window_settings.update_from_display(&main.window);
+                    }
+                    if destroyed || (close_requested && app.on_close_requested()) {
+                        *control_flow = winit::event_loop::ControlFlow::Exit;
+                    }
+                } else if let Some((viewport, window_app)) = windows.get_mut(&window_id) {
+                    // Only the main window quits the app when closed.
+                    let close_requested = viewport.on_event(
+                        &device,
+                        event,
+                        runner.clipboard.as_mut(),
+                        runner.quit_shortcut,
+                    );
+                    if destroyed || (close_requested && window_app.on_close_requested()) {
+                        close_window(&mut windows, window_id, &mut storage);
+                    }
                 }
-                input_to_egui(
-                    event,
-                    clipboard.as_mut(),
-                    &mut raw_input,
-                    control_flow,
-                    &mut modifier_state,
-                );
-                window.request_redraw(); // TODO: maybe only on some events?
             }
             winit::event::Event::LoopDestroyed => {
-                egui::app::set_value(
-                    &mut storage,
-                    WINDOW_KEY,
-                    &WindowSettings::from_display(&window),
-                );
-                egui::app::set_value(&mut storage, EGUI_MEMORY_KEY, &*ctx.memory());
+                for id in windows.keys().copied().collect::<Vec<_>>() {
+                    close_window(&mut windows, id, &mut storage);
+                }
+                save_state(&mut storage, &main.window, &mut window_settings, &main.ctx, &mut app);
                 app.on_exit(&mut storage);
-                storage.save();
+                if let Err(err) = storage.save() {
+                    log::error!("Failed to save app state: {}", err);
+                }
             }
             _ => (),
         }
@@ -0,0 +1,140 @@
+//! Where copied text goes and pasted text comes from.
+
+use ::clipboard::{ClipboardContext, ClipboardProvider};
+
+pub type ClipboardError = Box<dyn std::error::Error>;
+
+pub trait Clipboard {
+    fn get_text(&mut self) -> Result<String, ClipboardError>;
+
+    fn set_text(&mut self, text: String) -> Result<(), ClipboardError>;
+
+    /// The X11 primary selection, pasted with the middle mouse button.
+    /// `None` on platforms without one.
+    ///
+    /// Only read: egui doesn't tell us what is selected, so other apps can't paste from us.
+    fn get_primary(&mut self) -> Option<Result<String, ClipboardError>> {
+        None
+    }
+}
+
+/// The system clipboard, falling back to a [`MemoryClipboard`] if it isn't available.
+pub fn system_or_fallback() -> Box<dyn Clipboard> {
+    match SystemClipboard::new() {
+        Ok(clipboard) => Box::new(clipboard),
+        Err(err) => {
+            log::warn!("Failed to initialize clipboard, copied text stays in the app: {}", err);
+            Box::new(MemoryClipboard::default())
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(all(unix, not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))))]
+type PrimaryContext =
+    ::clipboard::x11_clipboard::X11ClipboardContext<::clipboard::x11_clipboard::Primary>;
+
+/// The clipboard of the desktop, and on X11 (including XWayland) also the primary selection.
+pub struct SystemClipboard {
+    context: ClipboardContext,
+    #[cfg(all(
+        unix,
+        not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+    ))]
+    primary: Option<PrimaryContext>,
+}
+
+impl SystemClipboard {
+    pub fn new() -> Result<Self, ClipboardError> {
+        Ok(Self {
+            context: ClipboardContext::new()?,
+            #[cfg(all(
+                unix,
+                not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+            ))]
+            primary: PrimaryContext::new().ok(),
+        })
+    }
+}
+
+impl Clipboard for SystemClipboard {
+    fn get_text(&mut self) -> Result<String, ClipboardError> {
+        self.context.get_contents()
+    }
+
+    fn set_text(&mut self, text: String) -> Result<(), ClipboardError> {
+        self.context.set_contents(text)
+    }
+
+    #[cfg(all(
+        unix,
+        not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+    ))]
+    fn get_primary(&mut self) -> Option<Result<String, ClipboardError>> {
+        self.primary.as_mut().map(|primary| primary.get_contents())
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// A clipboard that only lives as long as the app, for when there is no system clipboard.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryClipboard {
+    text: String,
+}
+
+impl Clipboard for MemoryClipboard {
+    fn get_text(&mut self) -> Result<String, ClipboardError> {
+        Ok(self.text.clone())
+    }
+
+    fn set_text(&mut self, text: String) -> Result<(), ClipboardError> {
+        self.text = text;
+        Ok(())
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// A clipboard for tests: pastes `text`, records everything copied and can be made to fail.
+#[derive(Clone, Debug, Default)]
+pub struct MockClipboard {
+    /// What is pasted.
+    pub text: String,
+    /// What is pasted with the middle mouse button. `None` acts like a platform without one.
+    pub primary: Option<String>,
+    /// Everything passed to `set_text`, oldest first.
+    pub copied: Vec<String>,
+    /// Make every call return an error.
+    pub fail: bool,
+}
+
+impl MockClipboard {
+    fn check(&self) -> Result<(), ClipboardError> {
+        if self.fail {
+            Err("MockClipboard::fail is set".into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Clipboard for MockClipboard {
+    fn get_text(&mut self) -> Result<String, ClipboardError> {
+        self.check()?;
+        Ok(self.text.clone())
+    }
+
+    fn set_text(&mut self, text: String) -> Result<(), ClipboardError> {
+        self.check()?;
+        self.copied.push(text.clone());
+        self.text = text;
+        Ok(())
+    }
+
+    fn get_primary(&mut self) -> Option<Result<String, ClipboardError>> {
+        let primary = self.primary.clone()?;
+        Some(self.check().map(|()| primary))
+    }
+}
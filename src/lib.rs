@@ -2,33 +2,63 @@
 #![allow(clippy::single_match)]
 
 mod backend;
+pub mod clipboard;
+pub mod migrations;
 mod painter;
 pub mod storage;
 
 use winit::{
     dpi,
     event::{ModifiersState, VirtualKeyCode},
-    event_loop::ControlFlow,
     window,
 };
 
 pub use backend::*;
-pub use painter::Painter;
+pub use painter::{PaintStats, Painter, TextureOptions, UserTextureId};
 
-use {clipboard::ClipboardProvider, egui::*};
+use {crate::clipboard::Clipboard, egui::*};
 
-pub use clipboard::ClipboardContext; // TODO: remove
+/// A key combination, e.g. the one that quits the app.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Shortcut {
+    pub modifiers: ModifiersState,
+    pub key: VirtualKeyCode,
+}
+
+impl Shortcut {
+    /// Cmd+Q on macOS, Win+Q elsewhere.
+    pub const LOGO_Q: Shortcut =
+        Shortcut { modifiers: ModifiersState::LOGO, key: VirtualKeyCode::Q };
+}
+
+/// A problem the user may want to know about, see `WGpuApp::on_notification`.
+/// Everything reported this way is also logged.
+#[derive(Clone, Debug)]
+pub struct Notification {
+    pub level: log::Level,
+    pub message: String,
+}
+
+fn notify(notifications: &mut Vec<Notification>, level: log::Level, message: String) {
+    log::log!(level, "{}", message);
+    notifications.push(Notification { level, message });
+}
 
+/// Returns `true` if the user asked to close the window,
+/// with the window's close button or by pressing `quit_shortcut`.
+/// It is up to the caller whether and when to actually close it.
 pub fn input_to_egui(
     event: winit::event::WindowEvent,
-    clipboard: Option<&mut ClipboardContext>,
+    clipboard: &mut dyn Clipboard,
     raw_input: &mut RawInput,
-    control_flow: &mut ControlFlow,
     modifier_state: &mut ModifiersState,
-) {
+    quit_shortcut: Option<Shortcut>,
+    notifications: &mut Vec<Notification>,
+) -> bool {
     use winit::event::WindowEvent::*;
+    let mut close_requested = false;
     match event {
-        CloseRequested | Destroyed => *control_flow = ControlFlow::Exit,
+        CloseRequested => close_requested = true,
 
         Resized(physical_size) => {
             raw_input.screen_size =
@@ -43,8 +73,18 @@ pub fn input_to_egui(
                     / (scale_factor as f32);
         }
 
-        MouseInput { state, .. } => {
+        MouseInput { state, button, .. } => {
             raw_input.mouse_down = state == winit::event::ElementState::Pressed;
+            if raw_input.mouse_down && button == winit::event::MouseButton::Middle {
+                match clipboard.get_primary() {
+                    Some(Ok(contents)) => raw_input.events.push(Event::Text(contents)),
+                    Some(Err(err)) => {
+                        let message = format!("Paste error: {}", err);
+                        notify(notifications, log::Level::Warn, message);
+                    }
+                    None => {}
+                }
+            }
         }
         CursorMoved { position, .. } => {
             raw_input.mouse_pos = Some(pos2(
@@ -55,39 +95,35 @@ pub fn input_to_egui(
         CursorLeft { .. } => {
             raw_input.mouse_pos = None;
         }
-        ReceivedCharacter(ch) => {
-            if printable_char(ch) {
-                raw_input.events.push(Event::Text(ch.to_string()));
-            }
+        ReceivedCharacter(ch) if printable_char(ch) => {
+            raw_input.events.push(Event::Text(ch.to_string()));
         }
         ModifiersChanged(input) => *modifier_state = input,
         KeyboardInput { input, .. } => {
             if let Some(virtual_keycode) = input.virtual_keycode {
-                if modifier_state.logo() && virtual_keycode == VirtualKeyCode::Q {
-                    *control_flow = ControlFlow::Exit;
+                let pressed = input.state == winit::event::ElementState::Pressed;
+                if pressed
+                    && quit_shortcut
+                        == Some(Shortcut { modifiers: *modifier_state, key: virtual_keycode })
+                {
+                    close_requested = true;
                 }
 
                 match virtual_keycode {
-                    VirtualKeyCode::Paste => {
-                        if let Some(clipboard) = clipboard {
-                            match clipboard.get_contents() {
-                                Ok(contents) => {
-                                    raw_input.events.push(Event::Text(contents));
-                                }
-                                Err(err) => {
-                                    eprintln!("Paste error: {}", err);
-                                }
-                            }
+                    VirtualKeyCode::Paste => match clipboard.get_text() {
+                        Ok(contents) => {
+                            raw_input.events.push(Event::Text(contents));
                         }
-                    }
+                        Err(err) => {
+                            let message = format!("Paste error: {}", err);
+                            notify(notifications, log::Level::Warn, message);
+                        }
+                    },
                     VirtualKeyCode::Copy => raw_input.events.push(Event::Copy),
                     VirtualKeyCode::Cut => raw_input.events.push(Event::Cut),
                     _ => {
                         if let Some(key) = translate_virtual_key_code(virtual_keycode) {
-                            raw_input.events.push(Event::Key {
-                                key,
-                                pressed: input.state == winit::event::ElementState::Pressed,
-                            });
+                            raw_input.events.push(Event::Key { key, pressed });
                         }
                     }
                 }
@@ -109,6 +145,7 @@ pub fn input_to_egui(
             // dbg!(event);
         }
     }
+    close_requested
 }
 
 /// Winit sends special keys (backspace, delete, F1, ...) as characters.
@@ -116,9 +153,9 @@ pub fn input_to_egui(
 /// We also ignore '\r', '\n', '\t'.
 /// Newlines are handled by the `Key::Enter` event.
 fn printable_char(chr: char) -> bool {
-    let is_in_private_use_area = '\u{e000}' <= chr && chr <= '\u{f8ff}'
-        || '\u{f0000}' <= chr && chr <= '\u{ffffd}'
-        || '\u{100000}' <= chr && chr <= '\u{10fffd}';
+    let is_in_private_use_area = ('\u{e000}'..='\u{f8ff}').contains(&chr)
+        || ('\u{f0000}'..='\u{ffffd}').contains(&chr)
+        || ('\u{100000}'..='\u{10fffd}').contains(&chr);
 
     !is_in_private_use_area && !chr.is_ascii_control()
 }
@@ -166,34 +203,69 @@ pub fn translate_cursor(cursor_icon: egui::CursorIcon) -> window::CursorIcon {
     }
 }
 
+/// The mouse cursor over a window.
+///
+/// egui 0.1 only asks for a handful of shapes (see `translate_cursor`),
+/// apps can use any of winit's or hide the cursor with `WGpuBackend::set_cursor`,
+/// or until further notice with `WGpuBackend::set_cursor_visible`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cursor {
+    Icon(window::CursorIcon),
+    Hidden,
+}
+
+/// Show `cursor` over `window`, where `current` is what was shown last (`None` at first).
+/// Does nothing if it didn't change.
+pub fn update_cursor(window: &window::Window, cursor: Cursor, current: &mut Option<Cursor>) {
+    if *current == Some(cursor) {
+        return;
+    }
+    match cursor {
+        Cursor::Icon(icon) => {
+            if *current == Some(Cursor::Hidden) {
+                window.set_cursor_visible(true);
+            }
+            window.set_cursor_icon(icon);
+        }
+        Cursor::Hidden => window.set_cursor_visible(false),
+    }
+    *current = Some(cursor);
+}
+
+/// Decides what happens to links egui wants to open.
+/// Gets the url and whether it should open in a new tab, errors are reported as notifications.
+///
+/// egui 0.1 doesn't say how a link should be opened, so `new_tab` is always `true` for now.
+pub type UrlHandler = dyn FnMut(&str, bool) -> Result<(), String>;
+
+/// Open links in the default browser. The default `UrlHandler`.
+pub fn open_in_browser(url: &str, _new_tab: bool) -> Result<(), String> {
+    webbrowser::open(url).map(|_| ()).map_err(|err| err.to_string())
+}
+
+/// A `UrlHandler` that only logs links instead of opening them.
+pub fn deny_all_urls(url: &str, _new_tab: bool) -> Result<(), String> {
+    log::info!("Not opening {}: opening links is disabled", url);
+    Ok(())
+}
+
+/// Everything but the cursor, see `update_cursor`.
 pub fn handle_output(
     output: egui::Output,
-    window: &window::Window,
-    clipboard: Option<&mut ClipboardContext>,
+    clipboard: &mut dyn Clipboard,
+    url_handler: &mut UrlHandler,
+    notifications: &mut Vec<Notification>,
 ) {
     if let Some(url) = output.open_url {
-        if let Err(err) = webbrowser::open(&url) {
-            eprintln!("Failed to open url: {}", err); // TODO show error in imgui
+        if let Err(err) = url_handler(&url, true) {
+            let message = format!("Failed to open {}: {}", url, err);
+            notify(notifications, log::Level::Warn, message);
         }
     }
 
     if !output.copied_text.is_empty() {
-        if let Some(clipboard) = clipboard {
-            if let Err(err) = clipboard.set_contents(output.copied_text) {
-                eprintln!("Copy/Cut error: {}", err);
-            }
-        }
-    }
-
-    window.set_cursor_icon(translate_cursor(output.cursor_icon));
-}
-
-pub fn init_clipboard() -> Option<ClipboardContext> {
-    match ClipboardContext::new() {
-        Ok(clipboard) => Some(clipboard),
-        Err(err) => {
-            eprintln!("Failed to initialize clipboard: {}", err);
-            None
+        if let Err(err) = clipboard.set_text(output.copied_text) {
+            notify(notifications, log::Level::Warn, format!("Copy/Cut error: {}", err));
         }
     }
 }
@@ -218,3 +290,107 @@ pub fn make_raw_input(window: &window::Window) -> egui::RawInput {
         ..Default::default()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::clipboard::MockClipboard, winit::event::WindowEvent};
+
+    fn device_id() -> winit::event::DeviceId {
+        unsafe { winit::event::DeviceId::dummy() }
+    }
+
+    #[allow(deprecated)]
+    fn key_press(key: VirtualKeyCode) -> WindowEvent<'static> {
+        WindowEvent::KeyboardInput {
+            device_id: device_id(),
+            input: winit::event::KeyboardInput {
+                scancode: 0,
+                state: winit::event::ElementState::Pressed,
+                virtual_keycode: Some(key),
+                modifiers: ModifiersState::empty(),
+            },
+            is_synthetic: false,
+        }
+    }
+
+    #[allow(deprecated)]
+    fn middle_click() -> WindowEvent<'static> {
+        WindowEvent::MouseInput {
+            device_id: device_id(),
+            state: winit::event::ElementState::Pressed,
+            button: winit::event::MouseButton::Middle,
+            modifiers: ModifiersState::empty(),
+        }
+    }
+
+    /// The events egui gets for `event`, and the notifications it caused.
+    fn input(
+        event: WindowEvent<'_>,
+        clipboard: &mut MockClipboard,
+    ) -> (Vec<Event>, Vec<Notification>) {
+        let mut raw_input = RawInput::default();
+        let mut notifications = Vec::new();
+        input_to_egui(
+            event,
+            clipboard,
+            &mut raw_input,
+            &mut ModifiersState::empty(),
+            None,
+            &mut notifications,
+        );
+        (raw_input.events, notifications)
+    }
+
+    fn copy(text: &str, clipboard: &mut MockClipboard) -> Vec<Notification> {
+        let output = Output { copied_text: text.to_owned(), ..Default::default() };
+        let mut notifications = Vec::new();
+        handle_output(output, clipboard, &mut deny_all_urls, &mut notifications);
+        notifications
+    }
+
+    #[test]
+    fn paste() {
+        let mut clipboard = MockClipboard { text: "pasted".to_owned(), ..Default::default() };
+        let (events, notifications) = input(key_press(VirtualKeyCode::Paste), &mut clipboard);
+        assert_eq!(events, [Event::Text("pasted".to_owned())]);
+        assert!(notifications.is_empty());
+    }
+
+    #[test]
+    fn copy_lands_in_clipboard() {
+        let mut clipboard = MockClipboard::default();
+        assert!(copy("copied", &mut clipboard).is_empty());
+        assert!(copy("", &mut clipboard).is_empty());
+        assert_eq!(clipboard.copied, ["copied"]);
+    }
+
+    #[test]
+    fn middle_click_pastes_primary() {
+        let mut clipboard =
+            MockClipboard { primary: Some("selected".to_owned()), ..Default::default() };
+        let (events, _) = input(middle_click(), &mut clipboard);
+        assert_eq!(events, [Event::Text("selected".to_owned())]);
+
+        // Without a primary selection there is nothing to paste.
+        let (events, notifications) = input(middle_click(), &mut MockClipboard::default());
+        assert!(events.is_empty() && notifications.is_empty());
+    }
+
+    #[test]
+    fn clipboard_errors_are_notified() {
+        let mut clipboard =
+            MockClipboard { primary: Some(String::new()), fail: true, ..Default::default() };
+        let paste: [fn() -> WindowEvent<'static>; 2] =
+            [|| key_press(VirtualKeyCode::Paste), middle_click];
+        for event in &paste {
+            let (events, notifications) = input(event(), &mut clipboard);
+            assert!(events.is_empty());
+            assert_eq!(notifications.len(), 1);
+            assert_eq!(notifications[0].level, log::Level::Warn);
+        }
+
+        let notifications = copy("copied", &mut clipboard);
+        assert_eq!(notifications.len(), 1);
+        assert!(clipboard.copied.is_empty());
+    }
+}
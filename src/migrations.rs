@@ -0,0 +1,262 @@
+//! Versioned values in a [`Storage`], so app state survives changes to its format.
+//!
+//! Every key with registered migrations gets its version stored next to it under
+//! `<key>.version`. Values that can't be parsed or migrated are copied to
+//! `<key>.quarantined.<timestamp>` instead of silently being replaced by defaults,
+//! and are treated as missing until the app stores a new value.
+
+use {
+    egui::app::Storage,
+    std::{collections::HashMap, time::SystemTime},
+};
+
+pub const VERSION_SUFFIX: &str = ".version";
+pub const QUARANTINE_SUFFIX: &str = ".quarantined";
+
+/// Upgrades a value by one version.
+pub type Migration = Box<dyn Fn(serde_json::Value) -> Result<serde_json::Value, String>>;
+
+/// The migrations of every versioned key.
+///
+/// ```ignore
+/// let migrations = Migrations::new()
+///     // version 0 -> 1: `zoom` used to be a percentage
+///     .add(egui::app::APP_KEY, |mut app| {
+///         app["zoom"] = (app["zoom"].as_f64().unwrap_or(100.0) / 100.0).into();
+///         Ok(app)
+///     });
+/// migrations.apply(&mut storage);
+/// ```
+#[derive(Default)]
+pub struct Migrations {
+    /// `migrations[key][n]` upgrades `key` from version `n` to `n + 1`.
+    migrations: HashMap<String, Vec<Migration>>,
+}
+
+impl Migrations {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Register the next migration of `key`.
+    /// The first call for a key upgrades version 0 (unversioned) to 1, the second 1 to 2 and so on.
+    pub fn add(
+        mut self,
+        key: &str,
+        migrate: impl Fn(serde_json::Value) -> Result<serde_json::Value, String> + 'static,
+    ) -> Self {
+        self.migrations.entry(key.to_owned()).or_default().push(Box::new(migrate));
+        self
+    }
+
+    /// The version values of `key` are written in by the current app.
+    pub fn current_version(&self, key: &str) -> u32 {
+        self.migrations.get(key).map_or(0, |migrations| migrations.len() as u32)
+    }
+
+    /// Bring every versioned key in `storage` up to its current version.
+    ///
+    /// Call this before reading anything from `storage`.
+    pub fn apply(&self, storage: &mut dyn Storage) {
+        for (key, migrations) in &self.migrations {
+            let current_version = migrations.len() as u32;
+
+            if let Some(quarantine) = Quarantine::get(storage, key) {
+                if quarantine.is_current(storage, key) {
+                    // Still the value that couldn't be used. Leave it unstamped, so it isn't
+                    // mistaken for a current one.
+                    continue;
+                }
+                // The app has stored a new value since, in the format of its version back then.
+                set_version(storage, key, quarantine.version);
+                storage.set_string(&quarantine_key(key), String::new());
+            }
+            let stored_version = stored_version(storage, key);
+
+            if let Some(raw) = storage.get_string(key).map(str::to_owned) {
+                if stored_version > current_version {
+                    log::warn!(
+                        "{:?} was written by a newer version ({} > {}), leaving it as is",
+                        key,
+                        stored_version,
+                        current_version
+                    );
+                    continue;
+                }
+                if stored_version < current_version {
+                    let migrated = serde_json::from_str(&raw)
+                        .map_err(|err| err.to_string())
+                        .and_then(|value| {
+                            migrations[stored_version as usize..]
+                                .iter()
+                                .try_fold(value, |value, migrate| migrate(value))
+                        })
+                        .and_then(|value| serde_json::to_string(&value).map_err(|e| e.to_string()));
+                    match migrated {
+                        Ok(migrated) => storage.set_string(key, migrated),
+                        Err(err) => {
+                            log::error!("Failed to migrate {:?}: {}", key, err);
+                            quarantine_at_version(storage, key, raw, current_version);
+                            continue;
+                        }
+                    }
+                }
+            }
+
+            // Also stamp keys that don't exist yet: the app will write them in the current format.
+            set_version(storage, key, current_version);
+        }
+    }
+}
+
+fn set_version(storage: &mut dyn Storage, key: &str, version: u32) {
+    storage.set_string(&format!("{}{}", key, VERSION_SUFFIX), version.to_string());
+}
+
+fn stored_version(storage: &dyn Storage, key: &str) -> u32 {
+    storage
+        .get_string(&format!("{}{}", key, VERSION_SUFFIX))
+        .and_then(|version| version.parse().ok())
+        .unwrap_or(0)
+}
+
+/// The latest quarantine of a key, stored as JSON under `<key>.quarantined`.
+#[derive(serde::Deserialize, serde::Serialize)]
+struct Quarantine {
+    /// Where the copy of the value is, `<key>.quarantined.<timestamp>`.
+    copy: String,
+    /// The version of the app that quarantined the value,
+    /// i.e. the format the app will write the key in from then on.
+    version: u32,
+}
+
+impl Quarantine {
+    fn get(storage: &dyn Storage, key: &str) -> Option<Self> {
+        serde_json::from_str(storage.get_string(&quarantine_key(key))?).ok()
+    }
+
+    /// Whether `key` still holds the quarantined value.
+    fn is_current(&self, storage: &dyn Storage, key: &str) -> bool {
+        storage.get_string(key).is_some()
+            && storage.get_string(key) == storage.get_string(&self.copy)
+    }
+}
+
+fn quarantine_key(key: &str) -> String {
+    format!("{}{}", key, QUARANTINE_SUFFIX)
+}
+
+/// Keep a copy of a value that couldn't be used under `<key>.quarantined.<timestamp>`,
+/// so it can be recovered by hand after the app has replaced it.
+/// Until then, `get_value` treats the key as missing.
+pub fn quarantine(storage: &mut dyn Storage, key: &str, raw: String) {
+    let version = stored_version(storage, key);
+    quarantine_at_version(storage, key, raw, version);
+}
+
+fn quarantine_at_version(storage: &mut dyn Storage, key: &str, raw: String, version: u32) {
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_millis());
+    let mut copy = format!("{}.{}", quarantine_key(key), timestamp);
+    // Never overwrite an earlier copy, even one from the same millisecond.
+    while storage.get_string(&copy).is_some() {
+        copy.push('+');
+    }
+    log::warn!("Quarantining {:?} as {:?}", key, copy);
+    storage.set_string(&copy, raw);
+    let quarantine = Quarantine { copy, version };
+    if let Ok(json) = serde_json::to_string(&quarantine) {
+        storage.set_string(&quarantine_key(key), json);
+    }
+}
+
+/// Like `egui::app::get_value`, but a value that fails to deserialize is quarantined
+/// instead of silently being replaced by a default later.
+/// Returns `None` for a quarantined value the app hasn't replaced yet.
+pub fn get_value<T: serde::de::DeserializeOwned>(
+    storage: &mut dyn Storage,
+    key: &str,
+) -> Option<T> {
+    if let Some(quarantine) = Quarantine::get(storage, key) {
+        if quarantine.is_current(storage, key) {
+            return None;
+        }
+    }
+    let raw = storage.get_string(key)?.to_owned();
+    match serde_json::from_str(&raw) {
+        Ok(value) => Some(value),
+        Err(err) => {
+            log::warn!("Failed to parse {:?}: {}", key, err);
+            quarantine(storage, key, raw);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct MapStorage(HashMap<String, String>);
+
+    impl Storage for MapStorage {
+        fn get_string(&self, key: &str) -> Option<&str> {
+            self.0.get(key).map(String::as_str)
+        }
+
+        fn set_string(&mut self, key: &str, value: String) {
+            self.0.insert(key.to_owned(), value);
+        }
+    }
+
+    fn failing() -> Migrations {
+        Migrations::new().add("app", |_| Err("broken".to_owned()))
+    }
+
+    #[test]
+    fn failed_migration_is_not_stamped() {
+        let mut storage = MapStorage::default();
+        storage.set_string("app", "1".to_owned());
+        failing().apply(&mut storage);
+
+        assert_eq!(stored_version(&storage, "app"), 0);
+        assert_eq!(get_value::<u32>(&mut storage, "app"), None);
+
+        // Applying again neither migrates nor quarantines the same value twice.
+        failing().apply(&mut storage);
+        assert_eq!(stored_version(&storage, "app"), 0);
+        let copies = storage.0.keys().filter(|key| key.starts_with("app.quarantined.")).count();
+        assert_eq!(copies, 1);
+    }
+
+    #[test]
+    fn replaced_value_is_stamped_with_the_quarantining_version() {
+        let mut storage = MapStorage::default();
+        storage.set_string("app", "1".to_owned());
+        failing().apply(&mut storage);
+
+        // The app stores a new value in its current format.
+        storage.set_string("app", "2".to_owned());
+        assert_eq!(get_value::<u32>(&mut storage, "app"), Some(2));
+        failing().apply(&mut storage);
+        assert_eq!(stored_version(&storage, "app"), 1);
+        assert_eq!(get_value::<u32>(&mut storage, "app"), Some(2));
+    }
+
+    #[test]
+    fn quarantine_keeps_earlier_copies() {
+        let mut storage = MapStorage::default();
+        quarantine(&mut storage, "app", "first".to_owned());
+        quarantine(&mut storage, "app", "second".to_owned());
+        let mut copies: Vec<_> = storage
+            .0
+            .iter()
+            .filter(|(key, _)| key.starts_with("app.quarantined."))
+            .map(|(_, value)| value.as_str())
+            .collect();
+        copies.sort_unstable();
+        assert_eq!(copies, ["first", "second"]);
+    }
+}
@@ -3,137 +3,239 @@
 use {
     egui::{
         paint::{PaintJobs, Vertex},
+        Rect,
     },
-    std::{borrow::Cow, mem, slice},
     inline_spirv as spv,
+    std::{borrow::Cow, collections::HashMap, mem, slice, sync::Arc},
     wgpu::util::{self, DeviceExt},
 };
 
 const VERT_SHADER: &[u32] = spv::include_spirv!("src/shaders/egui.vert", vert);
 const FRAG_SHADER: &[u32] = spv::include_spirv!("src/shaders/egui.frag", frag);
+const IMAGE_FRAG_SHADER: &[u32] = spv::include_spirv!("src/shaders/egui_image.frag", frag);
+
+/// How a texture is sampled when drawn.
+///
+/// The font atlas always uses `TextureOptions::default()`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextureOptions {
+    /// Used both when magnifying and minifying the texture.
+    pub filter: wgpu::FilterMode,
+    pub address_mode: wgpu::AddressMode,
+    /// Generate a full mip chain when the texture is uploaded,
+    /// so it stays clean when drawn smaller than its native size.
+    pub mipmaps: bool,
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        Self {
+            filter: wgpu::FilterMode::Nearest,
+            address_mode: wgpu::AddressMode::ClampToEdge,
+            mipmaps: false,
+        }
+    }
+}
+
+impl TextureOptions {
+    /// Linear filtering with mipmaps. A good choice for photos and icons that get scaled.
+    pub const LINEAR_MIPMAPPED: Self = Self {
+        filter: wgpu::FilterMode::Linear,
+        address_mode: wgpu::AddressMode::ClampToEdge,
+        mipmaps: true,
+    };
+
+    fn create_sampler(&self, device: &wgpu::Device) -> wgpu::Sampler {
+        device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(concat!(file!(), "::sampler")),
+            address_mode_u: self.address_mode,
+            address_mode_v: self.address_mode,
+            address_mode_w: self.address_mode,
+            mag_filter: self.filter,
+            min_filter: self.filter,
+            mipmap_filter: if self.mipmaps { self.filter } else { wgpu::FilterMode::Nearest },
+            ..Default::default()
+        })
+    }
+}
+
+/// What the painter did during one `paint_jobs` call.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PaintStats {
+    pub draw_calls: usize,
+    pub vertices: usize,
+    pub indices: usize,
+    /// Vertex, index, uniform and texture data written to the gpu.
+    pub bytes_uploaded: usize,
+    /// Font atlas and user texture uploads, including partial ones.
+    pub texture_uploads: usize,
+    /// Paint jobs that weren't drawn because their clip rect was empty or off-screen.
+    pub clip_rects_skipped: usize,
+}
+
+/// Handle to an image registered with [`Painter::register_user_texture`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct UserTextureId(pub(crate) u64);
 
 pub struct Painter {
-    pipeline: wgpu::RenderPipeline,
+    pipelines: Arc<Pipelines>,
     vertex_buffers: Vec<wgpu::Buffer>,
     index_buffers: Vec<wgpu::Buffer>,
     uniform_buffer: wgpu::Buffer,
     bind_group: wgpu::BindGroup,
+    font_sampler: wgpu::Sampler,
+    current_texture: Option<GpuTexture>,
+    user_textures: HashMap<UserTextureId, UserTexture>,
+    next_user_texture_id: u64,
+    /// Images queued with [`Painter::draw_user_texture`] for the next `paint_jobs`.
+    image_draws: Vec<ImageDraw>,
+    /// Uploads done since the last `paint_jobs`, e.g. by `register_user_texture`.
+    pending_stats: PaintStats,
+}
+
+/// The egui texture as it currently lives on the gpu.
+struct GpuTexture {
+    /// `egui::Texture::id` of the last upload
+    id: u64,
+    width: usize,
+    height: usize,
+    texture: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+    /// Copy of the last uploaded pixels, used to find the rows that changed.
+    pixels: Vec<u8>,
+}
+
+/// The parts of a `Painter` that don't depend on what is drawn,
+/// shared by the painters of all windows on the same device.
+struct Pipelines {
+    output_format: wgpu::TextureFormat,
+    pipeline: wgpu::RenderPipeline,
+    image_pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
     texture_bind_group_layout: wgpu::BindGroupLayout,
-    current_texture: Option<(u64, wgpu::BindGroup)>,
 }
 
-impl Painter {
-    pub fn new(device: &wgpu::Device, output_format: wgpu::TextureFormat) -> Painter {
+struct ImageDraw {
+    id: UserTextureId,
+    rect: Rect,
+    clip_rect: Rect,
+}
+
+struct UserTexture {
+    width: usize,
+    height: usize,
+    /// Kept so the texture can be uploaded again when the device is recreated.
+    rgba: Vec<u8>,
+    options: TextureOptions,
+    // Kept alive for as long as the bind group references it.
+    _texture: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Pipelines {
+    fn new(device: &wgpu::Device, output_format: wgpu::TextureFormat) -> Pipelines {
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some(concat!(file!(), "::bind_group_layout")),
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    count: None,
-                    ty: wgpu::BindingType::UniformBuffer { dynamic: false, min_binding_size: None },
-                    visibility: wgpu::ShaderStage::VERTEX,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    count: None,
-                    ty: wgpu::BindingType::Sampler { comparison: false },
-                    visibility: wgpu::ShaderStage::FRAGMENT,
-                },
-            ],
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                count: None,
+                ty: wgpu::BindingType::UniformBuffer { dynamic: false, min_binding_size: None },
+                visibility: wgpu::ShaderStage::VERTEX,
+            }],
         });
         let texture_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some(concat!(file!(), "::bind_group_layout")),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    count: None,
-                    ty: wgpu::BindingType::SampledTexture {
-                        component_type: wgpu::TextureComponentType::Float,
-                        dimension: wgpu::TextureViewDimension::D2,
-                        multisampled: false,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        count: None,
+                        ty: wgpu::BindingType::SampledTexture {
+                            component_type: wgpu::TextureComponentType::Float,
+                            dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        visibility: wgpu::ShaderStage::FRAGMENT,
                     },
-                    visibility: wgpu::ShaderStage::FRAGMENT,
-                }],
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        count: None,
+                        ty: wgpu::BindingType::Sampler { comparison: false },
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                    },
+                ],
             });
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some(concat!(file!(), "::pipeline_layout")),
             bind_group_layouts: &[&bind_group_layout, &texture_bind_group_layout],
             push_constant_ranges: &[],
         });
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some(concat!(file!(), "::pipeline")),
-            layout: Some(&pipeline_layout),
-            vertex_stage: wgpu::ProgrammableStageDescriptor {
-                module: &device.create_shader_module(wgpu::ShaderModuleSource::SpirV(
-                    Cow::Borrowed(VERT_SHADER),
-                )),
-                entry_point: "main",
-            },
-            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
-                module: &device.create_shader_module(wgpu::ShaderModuleSource::SpirV(
-                    Cow::Borrowed(FRAG_SHADER),
-                )),
-                entry_point: "main",
-            }),
-            rasterization_state: Some(wgpu::RasterizationStateDescriptor::default()),
-            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
-            color_states: &[wgpu::ColorStateDescriptor {
-                format: output_format,
-                color_blend: wgpu::BlendDescriptor {
-                    src_factor: wgpu::BlendFactor::One,
-                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                    operation: wgpu::BlendOperation::Add,
-                },
-                alpha_blend: wgpu::BlendDescriptor {
-                    src_factor: wgpu::BlendFactor::One,
-                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                    operation: wgpu::BlendOperation::Add,
-                },
-                write_mask: wgpu::ColorWrite::ALL,
-            }],
-            depth_stencil_state: None,
-            vertex_state: wgpu::VertexStateDescriptor {
-                index_format: wgpu::IndexFormat::Uint32,
-                vertex_buffers: &[wgpu::VertexBufferDescriptor {
-                    stride: mem::size_of::<egui::paint::Vertex>() as _,
-                    step_mode: wgpu::InputStepMode::Vertex,
-                    attributes: &wgpu::vertex_attr_array![0 => Float2, 1 => Ushort2, 2 => Uchar4],
-                }],
-            },
-            sample_count: 1,
-            sample_mask: !0,
-            alpha_to_coverage_enabled: false,
-        });
+        let vert_module = device
+            .create_shader_module(wgpu::ShaderModuleSource::SpirV(Cow::Borrowed(VERT_SHADER)));
+        let pipeline = create_pipeline(
+            device,
+            &pipeline_layout,
+            &vert_module,
+            &device
+                .create_shader_module(wgpu::ShaderModuleSource::SpirV(Cow::Borrowed(FRAG_SHADER))),
+            output_format,
+        );
+        let image_pipeline = create_pipeline(
+            device,
+            &pipeline_layout,
+            &vert_module,
+            &device.create_shader_module(wgpu::ShaderModuleSource::SpirV(Cow::Borrowed(
+                IMAGE_FRAG_SHADER,
+            ))),
+            output_format,
+        );
+        Pipelines {
+            output_format,
+            pipeline,
+            image_pipeline,
+            bind_group_layout,
+            texture_bind_group_layout,
+        }
+    }
+}
 
+impl Painter {
+    pub fn new(device: &wgpu::Device, output_format: wgpu::TextureFormat) -> Painter {
+        Painter::with_pipelines(device, Arc::new(Pipelines::new(device, output_format)))
+    }
+
+    /// A painter for another window on the same device, reusing the pipelines of this one.
+    pub fn new_sharing(&self, device: &wgpu::Device) -> Painter {
+        Painter::with_pipelines(device, self.pipelines.clone())
+    }
+
+    fn with_pipelines(device: &wgpu::Device, pipelines: Arc<Pipelines>) -> Painter {
         let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some(concat!(file!(), "::uniform_buffer")),
             size: mem::size_of::<Uniform>() as _,
             usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
             mapped_at_creation: false,
         });
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some(concat!(file!(), "::bind_group")),
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::Buffer(uniform_buffer.slice(..)),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
-                },
-            ],
+            layout: &pipelines.bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(uniform_buffer.slice(..)),
+            }],
         });
         Painter {
-            pipeline,
+            pipelines,
             vertex_buffers: Vec::new(),
             index_buffers: Vec::new(),
             uniform_buffer,
             bind_group,
-            texture_bind_group_layout,
+            font_sampler: TextureOptions::default().create_sampler(device),
             current_texture: None,
+            user_textures: HashMap::new(),
+            next_user_texture_id: 0,
+            image_draws: Vec::new(),
+            pending_stats: PaintStats::default(),
         }
     }
 
@@ -143,54 +245,203 @@ impl Painter {
         queue: &wgpu::Queue,
         texture: &egui::Texture,
     ) {
-        {
-            match self.current_texture.as_ref() {
-                Some(&(id, _)) if id == texture.id => return,
-                _ => (),
-            };
-        }
-
-        let size =
-            wgpu::Extent3d { width: texture.width as _, height: texture.height as _, depth: 1 };
+        match self.current_texture.as_mut() {
+            Some(current) if current.id == texture.id => return,
+            Some(current) if current.width == texture.width && current.height == texture.height => {
+                // Same dimensions: only write the rows that actually changed.
+                // The font atlas only ever gains glyphs, so this is usually a thin band.
+                let row_len = texture.width;
+                let mut changed_rows = texture
+                    .pixels
+                    .chunks(row_len)
+                    .zip(current.pixels.chunks(row_len))
+                    .enumerate()
+                    .filter(|(_, (new, old))| new != old)
+                    .map(|(row, _)| row);
+                let first_row = changed_rows.clone().next();
+                let last_row = changed_rows.next_back();
+                if let (Some(first_row), Some(last_row)) = (first_row, last_row) {
+                    let rows = first_row..last_row + 1;
+                    write_rows(queue, &current.texture, texture, rows.clone());
+                    self.pending_stats.texture_uploads += 1;
+                    self.pending_stats.bytes_uploaded += rows.len() * row_len;
+                    current.pixels[rows.start * row_len..rows.end * row_len]
+                        .copy_from_slice(&texture.pixels[rows.start * row_len..rows.end * row_len]);
+                }
+                current.id = texture.id;
+                return;
+            }
+            _ => (),
+        };
 
+        // First upload, or the atlas changed size: (re)allocate the gpu texture.
         let gpu_texture = device.create_texture(&wgpu::TextureDescriptor {
             label: Some(concat!(file!(), "::texture")),
-            size,
+            size: wgpu::Extent3d {
+                width: texture.width as _,
+                height: texture.height as _,
+                depth: 1,
+            },
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: wgpu::TextureFormat::R8Unorm,
             usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
         });
+        write_rows(queue, &gpu_texture, texture, 0..texture.height);
+        self.pending_stats.texture_uploads += 1;
+        self.pending_stats.bytes_uploaded += texture.pixels.len();
 
-        queue.write_texture(
-            wgpu::TextureCopyView {
-                texture: &gpu_texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            texture.pixels.as_slice(),
-            wgpu::TextureDataLayout {
-                offset: 0,
-                bytes_per_row: (texture.pixels.len() / texture.height) as _,
-                rows_per_image: texture.height as _,
-            },
-            size,
-        );
+        let bind_group = self.create_texture_bind_group(device, &gpu_texture, &self.font_sampler);
+        self.current_texture = Some(GpuTexture {
+            id: texture.id,
+            width: texture.width,
+            height: texture.height,
+            texture: gpu_texture,
+            bind_group,
+            pixels: texture.pixels.clone(),
+        });
+    }
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+    fn create_texture_bind_group(
+        &self,
+        device: &wgpu::Device,
+        texture: &wgpu::Texture,
+        sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some(concat!(file!(), "::bind_group")),
-            layout: &self.texture_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(
-                    &gpu_texture.create_view(&wgpu::TextureViewDescriptor::default()),
-                ),
-            }],
+            layout: &self.pipelines.texture_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(
+                        &texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    }
+
+    /// Upload an image so it can be drawn with [`Painter::draw_user_texture`].
+    ///
+    /// `rgba` is `width * height` premultiplied RGBA pixels, row by row.
+    pub fn register_user_texture(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        width: usize,
+        height: usize,
+        rgba: &[u8],
+        options: TextureOptions,
+    ) -> UserTextureId {
+        assert_eq!(rgba.len(), width * height * 4, "expected {}x{} RGBA pixels", width, height);
+
+        let id = UserTextureId(self.next_user_texture_id);
+        self.next_user_texture_id += 1;
+        self.upload_user_texture(device, queue, id, (width, height), rgba.to_vec(), options);
+        id
+    }
+
+    /// Like `register_user_texture`, but with an id picked by the caller.
+    /// Replaces the texture if `id` is already registered.
+    pub(crate) fn upload_user_texture(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        id: UserTextureId,
+        (width, height): (usize, usize),
+        rgba: Vec<u8>,
+        options: TextureOptions,
+    ) {
+        let mips = if options.mipmaps {
+            mip_chain(width, height, &rgba)
+        } else {
+            vec![(width, height, rgba.clone())]
+        };
+        let gpu_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(concat!(file!(), "::user_texture")),
+            size: wgpu::Extent3d { width: width as _, height: height as _, depth: 1 },
+            mip_level_count: mips.len() as _,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
         });
-        self.current_texture = Some((texture.id, bind_group));
+        for (mip_level, (mip_width, mip_height, pixels)) in mips.iter().enumerate() {
+            queue.write_texture(
+                wgpu::TextureCopyView {
+                    texture: &gpu_texture,
+                    mip_level: mip_level as _,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                pixels,
+                wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: (mip_width * 4) as _,
+                    rows_per_image: *mip_height as _,
+                },
+                wgpu::Extent3d { width: *mip_width as _, height: *mip_height as _, depth: 1 },
+            );
+            self.pending_stats.bytes_uploaded += pixels.len();
+        }
+        self.pending_stats.texture_uploads += 1;
+
+        let sampler = options.create_sampler(device);
+        let bind_group = self.create_texture_bind_group(device, &gpu_texture, &sampler);
+        self.user_textures.insert(
+            id,
+            UserTexture { width, height, rgba, options, _texture: gpu_texture, bind_group },
+        );
+    }
+
+    pub fn free_user_texture(&mut self, id: UserTextureId) {
+        self.user_textures.remove(&id);
+    }
+
+    /// Recreate all gpu resources on a new device, e.g. after the old one was lost.
+    ///
+    /// User textures keep their ids, the font texture is uploaded again on the next `paint_jobs`.
+    pub fn recreate(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let painter = Painter::new(device, self.pipelines.output_format);
+        self.recreate_with(painter, device, queue);
+    }
+
+    /// Like `recreate`, but reuses the pipelines of `other`, which must already be recreated.
+    pub fn recreate_sharing(
+        &mut self,
+        other: &Painter,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) {
+        let painter = other.new_sharing(device);
+        self.recreate_with(painter, device, queue);
     }
 
+    fn recreate_with(&mut self, mut painter: Painter, device: &wgpu::Device, queue: &wgpu::Queue) {
+        painter.next_user_texture_id = self.next_user_texture_id;
+        for (id, user_texture) in self.user_textures.drain() {
+            let UserTexture { width, height, rgba, options, .. } = user_texture;
+            painter.upload_user_texture(device, queue, id, (width, height), rgba, options);
+        }
+        *self = painter;
+    }
+
+    /// Draw a registered image into `rect` during the next `paint_jobs`, clipped to `clip_rect`
+    /// (usually `ui.clip_rect()`). Both are in points.
+    ///
+    /// egui paint jobs can't reference user textures yet, so the image is drawn right after the
+    /// last paint job with the same clip rect: on top of the egui window it belongs to,
+    /// but below windows painted later. Without such a job it is drawn on top of everything.
+    pub fn draw_user_texture(&mut self, id: UserTextureId, rect: Rect, clip_rect: Rect) {
+        self.image_draws.push(ImageDraw { id, rect, clip_rect });
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn paint_jobs<'r>(
         &'r mut self,
         jobs: PaintJobs,
@@ -200,68 +451,290 @@ impl Painter {
         queue: &wgpu::Queue,
         rpass: &mut wgpu::RenderPass<'r>,
         texture: &egui::Texture,
-    ) {
+    ) -> PaintStats {
         let logical_size = physical_size.to_logical(scale_factor);
         self.upload_texture(device, queue, texture);
-        let textures_bind_group = match self.current_texture.as_ref() {
-            Some((_, bg)) => bg,
-            _ => unreachable!(),
-        };
 
         queue.write_buffer(
             &self.uniform_buffer,
             0,
-            bytemuck::bytes_of(&Uniform {
-                screen_size: [logical_size.width, logical_size.height],
-                tex_size: [texture.width as f32, texture.height as f32],
-            }),
+            bytemuck::bytes_of(&Uniform { screen_size: [logical_size.width, logical_size.height] }),
         );
 
+        let mut stats = mem::take(&mut self.pending_stats);
+        stats.bytes_uploaded += mem::size_of::<Uniform>();
+
         self.vertex_buffers.clear();
         self.index_buffers.clear();
 
-        rpass.set_pipeline(&self.pipeline);
-        rpass.set_bind_group(0, &self.bind_group, &[]);
-        rpass.set_bind_group(1, &textures_bind_group, &[]);
-        for (_, triangles) in jobs.iter() {
-            // Safety: VertexPod is a transparent wrapper over Vertex, which _should_ already be a POD type
-            let vertex_pods = unsafe {
-                slice::from_raw_parts(
-                    triangles.vertices.as_ptr() as *const VertexPod,
-                    triangles.vertices.len(),
-                )
-            };
-            self.vertex_buffers.push(device.create_buffer_init(&util::BufferInitDescriptor {
-                label: None,
-                contents: &bytemuck::cast_slice(vertex_pods),
-                usage: wgpu::BufferUsage::VERTEX,
-            }));
-            self.index_buffers.push(device.create_buffer_init(&util::BufferInitDescriptor {
-                label: None,
-                contents: &bytemuck::cast_slice(triangles.indices.as_slice()),
-                usage: wgpu::BufferUsage::INDEX,
-            }));
+        // Every draw call in order: (scissor rect in physical pixels, index count, user texture)
+        let mut draws = Vec::with_capacity(jobs.len() + self.image_draws.len());
+        let mut last_job_with_clip_rect = HashMap::new();
+        for (job_index, (clip_rect, _)) in jobs.iter().enumerate() {
+            last_job_with_clip_rect.insert(rect_bits(clip_rect), job_index);
         }
+        let mut images_after_job: HashMap<usize, Vec<ImageDraw>> = HashMap::new();
+        // Images whose clip rect matches no paint job go on top.
+        let mut images_on_top = Vec::new();
+        let user_textures = &self.user_textures;
+        for image in self.image_draws.drain(..).filter(|draw| user_textures.contains_key(&draw.id))
+        {
+            match last_job_with_clip_rect.get(&rect_bits(&image.clip_rect)) {
+                Some(&job_index) => images_after_job.entry(job_index).or_default().push(image),
+                None => images_on_top.push(image),
+            }
+        }
+
+        for (job_index, (clip_rect, triangles)) in jobs.iter().enumerate() {
+            if let Some(scissor) = scissor_rect(*clip_rect, physical_size, scale_factor as f32) {
+                draws.push((scissor, triangles.indices.len(), None));
+                stats.vertices += triangles.vertices.len();
+                stats.indices += triangles.indices.len();
+                stats.bytes_uploaded += triangles.vertices.len() * mem::size_of::<Vertex>()
+                    + triangles.indices.len() * mem::size_of::<u32>();
 
-        for (((clip_rect, triangles), vertex_buffer), index_buffer) in
-            jobs.iter().zip(self.vertex_buffers.iter()).zip(self.index_buffers.iter())
+                // Safety: VertexPod is a transparent wrapper over Vertex, which _should_ already be a POD type
+                let vertex_pods = unsafe {
+                    slice::from_raw_parts(
+                        triangles.vertices.as_ptr() as *const VertexPod,
+                        triangles.vertices.len(),
+                    )
+                };
+                self.vertex_buffers.push(device.create_buffer_init(&util::BufferInitDescriptor {
+                    label: None,
+                    contents: bytemuck::cast_slice(vertex_pods),
+                    usage: wgpu::BufferUsage::VERTEX,
+                }));
+                self.index_buffers.push(device.create_buffer_init(&util::BufferInitDescriptor {
+                    label: None,
+                    contents: bytemuck::cast_slice(triangles.indices.as_slice()),
+                    usage: wgpu::BufferUsage::INDEX,
+                }));
+            } else {
+                stats.clip_rects_skipped += 1;
+            }
+
+            for image in images_after_job.remove(&job_index).unwrap_or_default() {
+                self.push_image(
+                    &image,
+                    physical_size,
+                    scale_factor,
+                    device,
+                    &mut draws,
+                    &mut stats,
+                );
+            }
+        }
+        for image in &images_on_top {
+            self.push_image(image, physical_size, scale_factor, device, &mut draws, &mut stats);
+        }
+
+        let font_bind_group = match self.current_texture.as_ref() {
+            Some(current) => &current.bind_group,
+            _ => unreachable!(),
+        };
+
+        rpass.set_bind_group(0, &self.bind_group, &[]);
+        let mut drawing_image = None;
+        for ((([x, y, width, height], index_count, image), vertex_buffer), index_buffer) in
+            draws.iter().zip(self.vertex_buffers.iter()).zip(self.index_buffers.iter())
         {
-            let clip_width = clip_rect.max.x - clip_rect.min.x;
-            let clip_height = clip_rect.max.y - clip_rect.min.y;
-            let scale = scale_factor as f32;
-            rpass.set_scissor_rect(
-                (scale * clip_rect.min.x) as _,
-                (scale * clip_rect.min.y) as _,
-                (scale * clip_width) as _,
-                (scale * clip_height) as _,
-            );
+            if drawing_image != Some(image.is_some()) {
+                drawing_image = Some(image.is_some());
+                rpass.set_pipeline(if image.is_some() {
+                    &self.pipelines.image_pipeline
+                } else {
+                    &self.pipelines.pipeline
+                });
+            }
+            match image {
+                Some(id) => rpass.set_bind_group(1, &self.user_textures[id].bind_group, &[]),
+                None => rpass.set_bind_group(1, font_bind_group, &[]),
+            }
+            rpass.set_scissor_rect(*x, *y, *width, *height);
             rpass.set_vertex_buffer(0, vertex_buffer.slice(..));
             rpass.set_index_buffer(index_buffer.slice(..));
-            rpass.draw_indexed(0..triangles.indices.len() as _, 0, 0..1)
+            rpass.draw_indexed(0..*index_count as _, 0, 0..1);
+            stats.draw_calls += 1;
         }
+
+        stats
     }
 }
 
+impl Painter {
+    /// Upload the quad of `image` and queue it in `draws`, unless it is clipped away.
+    fn push_image(
+        &mut self,
+        image: &ImageDraw,
+        physical_size: winit::dpi::PhysicalSize<f32>,
+        scale_factor: f64,
+        device: &wgpu::Device,
+        draws: &mut Vec<([u32; 4], usize, Option<UserTextureId>)>,
+        stats: &mut PaintStats,
+    ) {
+        let clip_rect = image.clip_rect.intersect(image.rect);
+        let scissor = match scissor_rect(clip_rect, physical_size, scale_factor as f32) {
+            Some(scissor) => scissor,
+            None => {
+                stats.clip_rects_skipped += 1;
+                return;
+            }
+        };
+        let user_texture = &self.user_textures[&image.id];
+        let (w, h) = (user_texture.width as u16, user_texture.height as u16);
+        let rect = image.rect;
+        let white = [255; 4];
+        let vertices = [
+            ImageVertex { pos: [rect.min.x, rect.min.y], uv: [0, 0], color: white },
+            ImageVertex { pos: [rect.max.x, rect.min.y], uv: [w, 0], color: white },
+            ImageVertex { pos: [rect.min.x, rect.max.y], uv: [0, h], color: white },
+            ImageVertex { pos: [rect.max.x, rect.max.y], uv: [w, h], color: white },
+        ];
+        self.vertex_buffers.push(device.create_buffer_init(&util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsage::VERTEX,
+        }));
+        self.index_buffers.push(device.create_buffer_init(&util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&[0u32, 1, 2, 2, 1, 3]),
+            usage: wgpu::BufferUsage::INDEX,
+        }));
+        draws.push((scissor, 6, Some(image.id)));
+        stats.vertices += 4;
+        stats.indices += 6;
+        stats.bytes_uploaded += mem::size_of_val(&vertices) + 6 * mem::size_of::<u32>();
+    }
+}
+
+/// `Rect` isn't `Hash`. Rects with the same bits are equal, which is all paint jobs need.
+fn rect_bits(rect: &Rect) -> [u32; 4] {
+    [rect.min.x.to_bits(), rect.min.y.to_bits(), rect.max.x.to_bits(), rect.max.y.to_bits()]
+}
+
+/// Convert a clip rect in points to a scissor rect `[x, y, width, height]` in physical pixels,
+/// clamped to the framebuffer. Returns `None` if nothing of it would be visible.
+fn scissor_rect(
+    clip_rect: Rect,
+    physical_size: winit::dpi::PhysicalSize<f32>,
+    scale: f32,
+) -> Option<[u32; 4]> {
+    let min_x = (scale * clip_rect.min.x).round().max(0.0).min(physical_size.width);
+    let min_y = (scale * clip_rect.min.y).round().max(0.0).min(physical_size.height);
+    let max_x = (scale * clip_rect.max.x).round().max(0.0).min(physical_size.width);
+    let max_y = (scale * clip_rect.max.y).round().max(0.0).min(physical_size.height);
+    if max_x <= min_x || max_y <= min_y {
+        None
+    } else {
+        Some([min_x as u32, min_y as u32, (max_x - min_x) as u32, (max_y - min_y) as u32])
+    }
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    vert_module: &wgpu::ShaderModule,
+    frag_module: &wgpu::ShaderModule,
+    output_format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(concat!(file!(), "::pipeline")),
+        layout: Some(layout),
+        vertex_stage: wgpu::ProgrammableStageDescriptor {
+            module: vert_module,
+            entry_point: "main",
+        },
+        fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+            module: frag_module,
+            entry_point: "main",
+        }),
+        rasterization_state: Some(wgpu::RasterizationStateDescriptor::default()),
+        primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+        color_states: &[wgpu::ColorStateDescriptor {
+            format: output_format,
+            // egui's colors are premultiplied. Blending alpha the same way keeps the
+            // framebuffer premultiplied too, which is what compositors expect of transparent windows.
+            color_blend: wgpu::BlendDescriptor {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha_blend: wgpu::BlendDescriptor {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation: wgpu::BlendOperation::Add,
+            },
+            write_mask: wgpu::ColorWrite::ALL,
+        }],
+        depth_stencil_state: None,
+        vertex_state: wgpu::VertexStateDescriptor {
+            index_format: wgpu::IndexFormat::Uint32,
+            vertex_buffers: &[wgpu::VertexBufferDescriptor {
+                stride: mem::size_of::<egui::paint::Vertex>() as _,
+                step_mode: wgpu::InputStepMode::Vertex,
+                attributes: &wgpu::vertex_attr_array![0 => Float2, 1 => Ushort2, 2 => Uchar4],
+            }],
+        },
+        sample_count: 1,
+        sample_mask: !0,
+        alpha_to_coverage_enabled: false,
+    })
+}
+
+/// Write the given rows of the egui texture into the gpu texture.
+fn write_rows(
+    queue: &wgpu::Queue,
+    gpu_texture: &wgpu::Texture,
+    texture: &egui::Texture,
+    rows: std::ops::Range<usize>,
+) {
+    let row_len = texture.width;
+    queue.write_texture(
+        wgpu::TextureCopyView {
+            texture: gpu_texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d { x: 0, y: rows.start as _, z: 0 },
+        },
+        &texture.pixels[rows.start * row_len..rows.end * row_len],
+        wgpu::TextureDataLayout {
+            offset: 0,
+            bytes_per_row: row_len as _,
+            rows_per_image: rows.len() as _,
+        },
+        wgpu::Extent3d { width: texture.width as _, height: rows.len() as _, depth: 1 },
+    );
+}
+
+/// Box-filter `rgba` down to 1x1, returning every level including the original.
+fn mip_chain(width: usize, height: usize, rgba: &[u8]) -> Vec<(usize, usize, Vec<u8>)> {
+    let mut mips = vec![(width, height, rgba.to_vec())];
+    while let Some(&(w, h, ref pixels)) = mips.last() {
+        if w == 1 && h == 1 {
+            break;
+        }
+        let (next_w, next_h) = ((w / 2).max(1), (h / 2).max(1));
+        let mut next = Vec::with_capacity(next_w * next_h * 4);
+        for y in 0..next_h {
+            for x in 0..next_w {
+                // Odd sizes: the last row/column is clamped rather than read out of bounds.
+                let xs = [2 * x, (2 * x + 1).min(w - 1)];
+                let ys = [2 * y, (2 * y + 1).min(h - 1)];
+                for channel in 0..4 {
+                    let sum: u32 = ys
+                        .iter()
+                        .flat_map(|&sy| xs.iter().map(move |&sx| (sy * w + sx) * 4 + channel))
+                        .map(|i| pixels[i] as u32)
+                        .sum();
+                    next.push(((sum + 2) / 4) as u8);
+                }
+            }
+        }
+        mips.push((next_w, next_h, next));
+    }
+    mips
+}
+
 #[repr(transparent)]
 #[derive(Debug, Copy, Clone)]
 struct VertexPod(Vertex);
@@ -269,12 +742,93 @@ struct VertexPod(Vertex);
 unsafe impl bytemuck::Zeroable for VertexPod {}
 unsafe impl bytemuck::Pod for VertexPod {}
 
+/// Same layout as `egui::paint::Vertex`, for the quads of user images.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct ImageVertex {
+    pos: [f32; 2],
+    uv: [u16; 2],
+    color: [u8; 4],
+}
+
+unsafe impl bytemuck::Zeroable for ImageVertex {}
+unsafe impl bytemuck::Pod for ImageVertex {}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 struct Uniform {
     screen_size: [f32; 2],
-    tex_size: [f32; 2],
 }
 
 unsafe impl bytemuck::Zeroable for Uniform {}
 unsafe impl bytemuck::Pod for Uniform {}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, egui::pos2};
+
+    fn size(width: f32, height: f32) -> winit::dpi::PhysicalSize<f32> {
+        winit::dpi::PhysicalSize::new(width, height)
+    }
+
+    #[test]
+    fn painter_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Painter>();
+    }
+
+    #[test]
+    fn scissor_rect_scales_and_rounds() {
+        let clip_rect = Rect::from_min_max(pos2(10.0, 20.2), pos2(30.0, 40.4));
+        assert_eq!(scissor_rect(clip_rect, size(100.0, 100.0), 2.0), Some([20, 40, 40, 41]));
+    }
+
+    #[test]
+    fn scissor_rect_is_clamped_to_the_framebuffer() {
+        let clip_rect = Rect::from_min_max(pos2(-10.0, -10.0), pos2(200.0, 30.0));
+        assert_eq!(scissor_rect(clip_rect, size(100.0, 50.0), 1.0), Some([0, 0, 100, 30]));
+    }
+
+    #[test]
+    fn invisible_scissor_rect_is_none() {
+        let size = size(100.0, 100.0);
+        let empty = Rect::from_min_max(pos2(10.0, 10.0), pos2(10.0, 50.0));
+        assert_eq!(scissor_rect(empty, size, 1.0), None);
+        let off_screen = Rect::from_min_max(pos2(200.0, 0.0), pos2(300.0, 50.0));
+        assert_eq!(scissor_rect(off_screen, size, 1.0), None);
+        let inverted = Rect::from_min_max(pos2(50.0, 50.0), pos2(10.0, 10.0));
+        assert_eq!(scissor_rect(inverted, size, 1.0), None);
+        // Less than half a pixel wide after scaling.
+        let thin = Rect::from_min_max(pos2(10.0, 10.0), pos2(10.2, 50.0));
+        assert_eq!(scissor_rect(thin, size, 1.0), None);
+    }
+
+    fn sizes(mips: &[(usize, usize, Vec<u8>)]) -> Vec<(usize, usize)> {
+        mips.iter().map(|&(width, height, _)| (width, height)).collect()
+    }
+
+    #[test]
+    fn mip_chain_averages_down_to_one_pixel() {
+        let rgba = [0, 0, 0, 255, 100, 0, 0, 255, 200, 0, 0, 255, 100, 0, 0, 255];
+        let mips = mip_chain(2, 2, &rgba);
+        assert_eq!(sizes(&mips), [(2, 2), (1, 1)]);
+        assert_eq!(mips[0].2, rgba);
+        assert_eq!(mips[1].2, [100, 0, 0, 255]);
+    }
+
+    #[test]
+    fn mip_chain_of_odd_sizes() {
+        assert_eq!(sizes(&mip_chain(5, 3, &[7; 5 * 3 * 4])), [(5, 3), (2, 1), (1, 1)]);
+        let mips = mip_chain(1, 5, &[9; 5 * 4]);
+        assert_eq!(sizes(&mips), [(1, 5), (1, 2), (1, 1)]);
+        // The clamped row and column are read in place of the missing ones.
+        assert!(mips.iter().all(|(_, _, pixels)| pixels.iter().all(|&value| value == 9)));
+        let mips = mip_chain(3, 1, &[0, 0, 0, 0, 4, 4, 4, 4, 255, 255, 255, 255]);
+        assert_eq!(mips[1].2, [2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn mip_chain_of_one_pixel() {
+        assert_eq!(sizes(&mip_chain(1, 1, &[1, 2, 3, 4])), [(1, 1)]);
+    }
+}
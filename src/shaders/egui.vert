@@ -1,8 +1,6 @@
-
 #version 450
 layout(set = 0, binding = 0) uniform Sizes {
     vec2 u_screen_size;
-    vec2 u_tex_size;
 };
 layout(location = 0) in vec2 a_pos;
 layout(location = 1) in uvec2 a_tc;
@@ -14,5 +12,6 @@ void main() {
     gl_Position =
       vec4(2.0 * a_pos.x / u_screen_size.x - 1.0, 1.0 - 2.0 * a_pos.y / u_screen_size.y, 0.0, 1.0);
     v_rgba = vec4(a_srgba / 255.0);
-    v_tc = a_tc / u_tex_size;
-}
\ No newline at end of file
+    // texel coordinates, normalized in the fragment shader where the texture size is known
+    v_tc = vec2(a_tc);
+}
@@ -0,0 +1,13 @@
+#version 450
+layout(set = 1, binding = 0) uniform texture2D u_texture;
+layout(set = 1, binding = 1) uniform sampler u_sampler;
+
+layout(location = 0) in vec4 v_rgba;
+layout(location = 1) in vec2 v_tc;
+layout(location = 0) out vec4 f_color;
+
+// Same as egui.frag, but for premultiplied rgba user images instead of the single channel font atlas.
+void main() {
+    vec2 uv = v_tc / vec2(textureSize(sampler2D(u_texture, u_sampler), 0));
+    f_color = v_rgba * texture(sampler2D(u_texture, u_sampler), uv);
+}
@@ -1,25 +1,462 @@
-use std::collections::HashMap;
+use fs2::FileExt;
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
 
 // ----------------------------------------------------------------------------
 
-/// A key-value store backed by a JSON file on disk.
+/// A `Storage` that `backend::run` knows how to persist.
+///
+/// Implement this to keep settings somewhere other than a JSON file,
+/// e.g. an existing config database or an encrypted store.
+pub trait PersistentStorage: egui::app::Storage {
+    /// Persist everything that changed since the last save.
+    fn save(&mut self) -> io::Result<()>;
+
+    /// Like `save`, but may do the actual work in the background. Errors are only reported.
+    fn save_in_background(&mut self) {
+        if let Err(err) = self.save() {
+            log::error!("Failed to save app state: {}", err);
+        }
+    }
+
+    /// When `backend::run` should save next, or `None` to only save on exit.
+    fn next_autosave(&self) -> Option<Instant> {
+        None
+    }
+
+    /// Pick up changes made to the store by someone else, e.g. an edited settings file.
+    /// Called by `backend::run` every frame, and returns the keys whose values changed.
+    fn reload_external_changes(&mut self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// When `backend::run` should wake up to call `reload_external_changes`, if ever.
+    fn next_reload_check(&self) -> Option<Instant> {
+        None
+    }
+}
+
+/// What to do when a key changed on disk while this process has unsaved changes to it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Keep the value of this process. It overwrites the external change on the next save.
+    KeepLocal,
+    /// Take the external value and drop the unsaved change.
+    TakeExternal,
+}
+
+// ----------------------------------------------------------------------------
+
+/// A key-value store that only lives in memory. Useful for tests and throwaway sessions.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStorage {
+    kv: HashMap<String, String>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl egui::app::Storage for MemoryStorage {
+    fn get_string(&self, key: &str) -> Option<&str> {
+        self.kv.get(key).map(String::as_str)
+    }
+
+    fn set_string(&mut self, key: &str, value: String) {
+        self.kv.insert(key.to_owned(), value);
+    }
+}
+
+impl PersistentStorage for MemoryStorage {
+    fn save(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// A key-value store backed by a JSON, RON or TOML file on disk, see [`Format`].
 /// Used to restore egui state, wgpu window position/size and app state.
+///
+/// The file is only read and written while holding an advisory lock on `<path>.lock`,
+/// so several instances of an app can share it. See also [`FileStorage::with_merge_on_save`].
 pub struct FileStorage {
-    path: String,
+    path: PathBuf,
     kv: HashMap<String, String>,
-    dirty: bool,
+    /// Keys set since the last save.
+    changed_keys: HashSet<String>,
+    merge_on_save: bool,
+    autosave_interval: Option<Duration>,
+    last_save: Instant,
+    background_save: Option<BackgroundSave>,
+    load_error: Option<StorageError>,
+    /// The file contents as of the last load or save, to tell external changes from our own.
+    on_disk: HashMap<String, String>,
+    watch: Option<Watch>,
+}
+
+struct Watch {
+    interval: Duration,
+    policy: ConflictPolicy,
+    next_check: Instant,
+    modified: Option<SystemTime>,
+}
+
+struct BackgroundSave {
+    /// Receives what was written, once the save thread is done.
+    result: mpsc::Receiver<io::Result<HashMap<String, String>>>,
+    /// The keys being saved, to be marked as changed again if the save fails.
+    keys: HashSet<String>,
 }
 
 impl FileStorage {
-    pub fn from_path(path: String) -> Self {
-        Self { kv: read_json(&path).unwrap_or_default(), path, dirty: false }
+    /// Starts out empty if the file doesn't exist or can't be read, see [`FileStorage::load_error`].
+    pub fn from_path(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        // Without a lock file (e.g. the directory doesn't exist) we still try to read.
+        let _lock = FileLock::acquire(&path).ok();
+        let (kv, load_error) = match read_kv(&path) {
+            Ok(kv) => (kv.unwrap_or_default(), None),
+            Err(err) => (Default::default(), Some(err)),
+        };
+        Self {
+            on_disk: kv.clone(),
+            kv,
+            path,
+            changed_keys: Default::default(),
+            merge_on_save: false,
+            autosave_interval: None,
+            last_save: Instant::now(),
+            background_save: None,
+            load_error,
+            watch: None,
+        }
+    }
+
+    /// Why the file couldn't be loaded, if it existed but couldn't be used.
+    ///
+    /// A corrupt file has been moved aside by then, so saving won't overwrite it.
+    pub fn load_error(&self) -> Option<&StorageError> {
+        self.load_error.as_ref()
+    }
+
+    /// Store the settings of the app in the per-user config directory of the platform,
+    /// as `<config dir>/<app_id>/settings.json`. The directories are created if needed.
+    ///
+    /// See [`config_dir`] for where that is.
+    pub fn from_app_id(app_id: &str) -> io::Result<Self> {
+        let dir = config_dir()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config directory"))?
+            .join(app_id);
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self::from_path(dir.join("settings.json")))
+    }
+
+    /// When saving, re-read the file and only write the keys this process changed.
+    ///
+    /// Without this, the instance of an app that saves last overwrites the changes of all others.
+    pub fn with_merge_on_save(mut self, merge_on_save: bool) -> Self {
+        self.merge_on_save = merge_on_save;
+        self
+    }
+
+    /// Check the file for changes made by someone else every `interval`, and reload them.
+    ///
+    /// `backend::run` passes the changed keys on to `WGpuApp::on_storage_changed`.
+    pub fn with_watch(mut self, interval: Duration, policy: ConflictPolicy) -> Self {
+        self.watch = Some(Watch {
+            interval,
+            policy,
+            next_check: Instant::now() + interval,
+            modified: modified_time(&self.path),
+        });
+        self
+    }
+
+    /// Have `backend::run` save the state every `interval`, instead of only on exit.
+    pub fn with_autosave_interval(mut self, interval: Duration) -> Self {
+        self.autosave_interval = Some(interval);
+        self
+    }
+
+    /// When the next autosave should happen, if autosave is enabled.
+    pub fn next_autosave(&self) -> Option<Instant> {
+        self.autosave_interval.map(|interval| self.last_save + interval)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write the store to disk if anything changed.
+    ///
+    /// The file is replaced atomically, so a crash mid-save leaves the previous version intact.
+    /// That previous version is also kept next to it with a `.bak` extension.
+    pub fn save(&mut self) -> io::Result<()> {
+        self.join_background_save();
+        self.last_save = Instant::now();
+        if !self.changed_keys.is_empty() {
+            let merge_keys = if self.merge_on_save { Some(&self.changed_keys) } else { None };
+            let written = write_kv(&self.path, &self.kv, merge_keys)?;
+            self.wrote_file();
+            self.changed_keys.clear();
+            self.on_disk = written.clone();
+            self.kv = written;
+        }
+        Ok(())
+    }
+
+    /// Like `save`, but writes the file on a background thread so the frame isn't blocked.
+    ///
+    /// Does nothing if nothing changed or the previous background save is still running,
+    /// e.g. because another instance holds the lock. Either way the next autosave is an
+    /// interval from now, so `next_autosave` never stays in the past.
+    pub fn save_in_background(&mut self) {
+        self.last_save = Instant::now();
+        if self.poll_background_save() {
+            return;
+        }
+        if !self.changed_keys.is_empty() {
+            let path = self.path.clone();
+            let kv = self.kv.clone();
+            let keys = std::mem::take(&mut self.changed_keys);
+            let merge_keys = if self.merge_on_save { Some(keys.clone()) } else { None };
+            let (sender, receiver) = mpsc::channel();
+            thread::spawn(move || {
+                let _ = sender.send(write_kv(&path, &kv, merge_keys.as_ref()));
+            });
+            self.background_save = Some(BackgroundSave { result: receiver, keys });
+        }
+    }
+
+    /// Handle the result of the background save if it is done.
+    /// Returns `true` while it is still running.
+    fn poll_background_save(&mut self) -> bool {
+        if let Some(background_save) = &self.background_save {
+            match background_save.result.try_recv() {
+                Err(mpsc::TryRecvError::Empty) => return true,
+                result => self.handle_background_save_result(result.ok()),
+            }
+        }
+        false
+    }
+
+    fn join_background_save(&mut self) {
+        if let Some(background_save) = &self.background_save {
+            let result = background_save.result.recv().ok();
+            self.handle_background_save_result(result);
+        }
+    }
+
+    /// So the watch doesn't take our own save for an external change.
+    fn wrote_file(&mut self) {
+        if let Some(watch) = &mut self.watch {
+            watch.modified = modified_time(&self.path);
+        }
+    }
+
+    /// `None` means the save thread died without reporting back.
+    fn handle_background_save_result(
+        &mut self,
+        result: Option<io::Result<HashMap<String, String>>>,
+    ) {
+        let keys = match self.background_save.take() {
+            Some(background_save) => background_save.keys,
+            None => return,
+        };
+        match result {
+            Some(Ok(written)) => {
+                self.wrote_file();
+                self.on_disk = written.clone();
+                // Pick up what other instances saved, without undoing our newer changes.
+                for (key, value) in written {
+                    if !self.changed_keys.contains(&key) {
+                        self.kv.insert(key, value);
+                    }
+                }
+            }
+            Some(Err(err)) => {
+                log::error!("Failed to save {}: {}", self.path.display(), err);
+                // Try again next time.
+                self.changed_keys.extend(keys);
+            }
+            None => {
+                log::error!("Failed to save {}: background save panicked", self.path.display());
+                self.changed_keys.extend(keys);
+            }
+        }
+    }
+}
+
+/// Write `kv` to `path` while holding its lock, and return what was written.
+///
+/// With `merge_keys`, the file is read again first and only those keys are taken from `kv`.
+fn write_kv(
+    path: &Path,
+    kv: &HashMap<String, String>,
+    merge_keys: Option<&HashSet<String>>,
+) -> io::Result<HashMap<String, String>> {
+    let _lock = FileLock::acquire(path)?;
+    let merged = match merge_keys {
+        Some(merge_keys) => match parse_kv(path) {
+            Ok(on_disk) => {
+                let mut merged = on_disk.unwrap_or_default();
+                for key in merge_keys {
+                    if let Some(value) = kv.get(key) {
+                        merged.insert(key.clone(), value.clone());
+                    }
+                }
+                merged
+            }
+            // Nothing to merge with. The corrupt file is kept as `.bak` by `write_atomically`.
+            Err(StorageError::Corrupt { .. }) => kv.clone(),
+            Err(StorageError::Io { error, .. }) => return Err(error),
+        },
+        None => kv.clone(),
+    };
+    let text = Format::from_path(path)
+        .write(&merged)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    write_atomically(path, |writer| writer.write_all(text.as_bytes()))?;
+    Ok(merged)
+}
+
+/// An advisory lock on `<path>.lock`, held until dropped.
+struct FileLock(std::fs::File);
+
+impl FileLock {
+    /// Blocks until no other process holds the lock.
+    fn acquire(path: &Path) -> io::Result<Self> {
+        let file = Self::open(path)?;
+        file.lock_exclusive()?;
+        Ok(FileLock(file))
     }
 
-    pub fn save(&mut self) {
-        if self.dirty {
-            serde_json::to_writer(std::fs::File::create(&self.path).unwrap(), &self.kv).unwrap();
-            self.dirty = false;
+    /// Returns `Ok(None)` right away if another process holds the lock.
+    fn try_acquire(path: &Path) -> io::Result<Option<Self>> {
+        let file = Self::open(path)?;
+        match file.try_lock_exclusive() {
+            Ok(()) => Ok(Some(FileLock(file))),
+            Err(err) if err.kind() == fs2::lock_contended_error().kind() => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn open(path: &Path) -> io::Result<std::fs::File> {
+        std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(with_added_extension(path, "lock"))
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = self.0.unlock();
+    }
+}
+
+impl FileStorage {
+    /// Reload the keys that changed on disk since we last read or wrote the file,
+    /// if the watch interval has passed. Returns the keys whose values changed.
+    pub fn reload_external_changes(&mut self) -> Vec<String> {
+        match &mut self.watch {
+            Some(watch) if watch.next_check <= Instant::now() => {
+                watch.next_check = Instant::now() + watch.interval;
+            }
+            _ => return Vec::new(),
+        }
+        // Our own background save would look like an external change until it reports back.
+        if self.poll_background_save() {
+            return Vec::new();
+        }
+        let watch = match &mut self.watch {
+            Some(watch) => watch,
+            None => return Vec::new(),
+        };
+
+        let modified = modified_time(&self.path);
+        if modified == watch.modified {
+            return Vec::new();
+        }
+
+        let on_disk: HashMap<String, String> = {
+            // This runs on the UI thread, so don't wait for another instance that is saving.
+            let _lock = match FileLock::try_acquire(&self.path) {
+                Ok(Some(lock)) => Some(lock),
+                Ok(None) => return Vec::new(),
+                // Without a lock file we still try to read.
+                Err(_) => None,
+            };
+            // Probably another process in the middle of editing it: leave the file where it is
+            // and try again at the next check.
+            match parse_kv(&self.path) {
+                Ok(on_disk) => on_disk.unwrap_or_default(),
+                Err(err) => {
+                    log::warn!("Failed to reload, retrying later: {}", err);
+                    return Vec::new();
+                }
+            }
+        };
+        watch.modified = modified;
+        let policy = watch.policy;
+
+        let mut changed = Vec::new();
+        for (key, value) in &on_disk {
+            if self.on_disk.get(key) == Some(value) {
+                continue;
+            }
+            if self.changed_keys.contains(key) {
+                match policy {
+                    ConflictPolicy::KeepLocal => continue,
+                    ConflictPolicy::TakeExternal => {
+                        self.changed_keys.remove(key);
+                    }
+                }
+            }
+            if self.kv.get(key) != Some(value) {
+                self.kv.insert(key.clone(), value.clone());
+                changed.push(key.clone());
+            }
         }
+        self.on_disk = on_disk;
+        changed
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+impl PersistentStorage for FileStorage {
+    fn save(&mut self) -> io::Result<()> {
+        FileStorage::save(self)
+    }
+
+    fn save_in_background(&mut self) {
+        FileStorage::save_in_background(self)
+    }
+
+    fn next_autosave(&self) -> Option<Instant> {
+        FileStorage::next_autosave(self)
+    }
+
+    fn reload_external_changes(&mut self) -> Vec<String> {
+        FileStorage::reload_external_changes(self)
+    }
+
+    fn next_reload_check(&self) -> Option<Instant> {
+        self.watch.as_ref().map(|watch| watch.next_check)
     }
 }
 
@@ -31,42 +468,322 @@ impl egui::app::Storage for FileStorage {
     fn set_string(&mut self, key: &str, value: String) {
         if self.kv.get(key) != Some(&value) {
             self.kv.insert(key.to_owned(), value);
-            self.dirty = true;
+            self.changed_keys.insert(key.to_owned());
         }
     }
 }
 
 // ----------------------------------------------------------------------------
 
-pub fn read_json<T>(memory_json_path: impl AsRef<std::path::Path>) -> Option<T>
+/// Environment variable that overrides [`config_dir`], e.g. to keep tests away from real settings.
+pub const CONFIG_DIR_ENV_VAR: &str = "EGUI_WGPU_CONFIG_DIR";
+
+/// The per-user directory for app settings:
+///
+/// * `$EGUI_WGPU_CONFIG_DIR`, if set
+/// * Linux and other unixes: `$XDG_CONFIG_HOME`, falling back to `$HOME/.config`
+/// * macOS: `$HOME/Library/Application Support`
+/// * Windows: `%APPDATA%`
+pub fn config_dir() -> Option<PathBuf> {
+    fn env_dir(name: &str) -> Option<PathBuf> {
+        std::env::var_os(name).filter(|dir| !dir.is_empty()).map(PathBuf::from)
+    }
+
+    if let Some(dir) = env_dir(CONFIG_DIR_ENV_VAR) {
+        return Some(dir);
+    }
+
+    if cfg!(windows) {
+        env_dir("APPDATA")
+    } else if cfg!(target_os = "macos") {
+        env_dir("HOME").map(|home| home.join("Library").join("Application Support"))
+    } else {
+        env_dir("XDG_CONFIG_HOME").or_else(|| env_dir("HOME").map(|home| home.join(".config")))
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// Why a settings file couldn't be loaded.
+#[derive(Debug)]
+pub enum StorageError {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    /// The file couldn't be parsed. When loading, it is renamed to `moved_to` so it can be
+    /// recovered by hand, unless that fails too.
+    Corrupt {
+        path: PathBuf,
+        error: Box<dyn std::error::Error + Send + Sync>,
+        moved_to: Option<PathBuf>,
+    },
+}
+
+impl std::fmt::Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageError::Io { path, error } => {
+                write!(f, "Failed to read {}: {}", path.display(), error)
+            }
+            StorageError::Corrupt { path, error, moved_to: Some(moved_to) } => write!(
+                f,
+                "Failed to parse {}: {}. It was moved to {}",
+                path.display(),
+                error,
+                moved_to.display()
+            ),
+            StorageError::Corrupt { path, error, moved_to: None } => {
+                write!(f, "Failed to parse {}: {}", path.display(), error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for StorageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StorageError::Io { error, .. } => Some(error),
+            StorageError::Corrupt { error, .. } => Some(&**error),
+        }
+    }
+}
+
+/// Returns `Ok(None)` if the file doesn't exist.
+///
+/// A file that can't be parsed is renamed to `<path>.corrupt-<timestamp>`,
+/// so whatever is saved to `path` next doesn't overwrite it.
+pub fn read_json<T>(json_path: impl AsRef<Path>) -> Result<Option<T>, StorageError>
 where
     T: serde::de::DeserializeOwned,
 {
-    match std::fs::File::open(memory_json_path) {
-        Ok(file) => {
-            let reader = std::io::BufReader::new(file);
-            match serde_json::from_reader(reader) {
-                Ok(value) => Some(value),
-                Err(err) => {
-                    eprintln!("ERROR: Failed to parse json: {}", err);
-                    None
+    read_file(json_path.as_ref(), |text| Ok(serde_json::from_str(text)?))
+}
+
+/// Read and parse a file. Returns `Ok(None)` if it doesn't exist.
+/// A file that can't be parsed is moved aside, see [`read_json`].
+fn read_file<T>(
+    path: &Path,
+    parse: impl FnOnce(&str) -> Result<T, Box<dyn std::error::Error + Send + Sync>>,
+) -> Result<Option<T>, StorageError> {
+    match parse_file(path, parse) {
+        Err(StorageError::Corrupt { path, error, .. }) => {
+            let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+            let mut corrupt_path = with_added_extension(&path, &format!("corrupt-{}", timestamp));
+            // Never overwrite an earlier copy, even one from the same second.
+            let mut copy = 1;
+            while corrupt_path.exists() {
+                copy += 1;
+                corrupt_path =
+                    with_added_extension(&path, &format!("corrupt-{}-{}", timestamp, copy));
+            }
+            let moved_to = std::fs::rename(&path, &corrupt_path).ok().map(|()| corrupt_path);
+            Err(StorageError::Corrupt { path, error, moved_to })
+        }
+        result => result,
+    }
+}
+
+/// Like [`read_file`], but leaves a file that can't be parsed where it is.
+fn parse_file<T>(
+    path: &Path,
+    parse: impl FnOnce(&str) -> Result<T, Box<dyn std::error::Error + Send + Sync>>,
+) -> Result<Option<T>, StorageError> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(StorageError::Io { path: path.to_owned(), error }),
+    };
+    // Binary garbage or a cut off character is as corrupt as bad syntax.
+    match std::str::from_utf8(&bytes).map_err(Into::into).and_then(parse) {
+        Ok(value) => Ok(Some(value)),
+        Err(error) => Err(StorageError::Corrupt { path: path.to_owned(), error, moved_to: None }),
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// How `FileStorage` writes its file, chosen by the file extension.
+///
+/// Values are inlined as real objects rather than as JSON strings, so the file is easy to read
+/// and edit by hand. Files written by older versions, where every value is a JSON string inside
+/// JSON, still load.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// `.json`, and any unknown extension. Pretty-printed.
+    Json,
+    /// `.ron`
+    Ron,
+    /// `.toml`. TOML has no null, so `None` fields are left out. Values TOML can't hold
+    /// otherwise, e.g. a null list element or an integer above `i64::MAX`, are kept as JSON.
+    Toml,
+}
+
+type Document = std::collections::BTreeMap<String, serde_json::Value>;
+
+/// Marks a file with inlined values. Files without it store every value as a JSON string.
+const FORMAT_KEY: &str = "$format";
+const FORMAT_INLINE: &str = "inline";
+/// Values that aren't valid JSON, e.g. quarantined ones, are kept as plain strings under here.
+const RAW_KEY: &str = "$raw";
+
+impl Format {
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("ron") => Format::Ron,
+            Some("toml") => Format::Toml,
+            _ => Format::Json,
+        }
+    }
+
+    fn parse(
+        self,
+        text: &str,
+    ) -> Result<HashMap<String, String>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut document: Document = match self {
+            Format::Json => serde_json::from_str(text)?,
+            Format::Ron => ron::from_str(text)?,
+            Format::Toml => toml::from_str(text)?,
+        };
+        let inline = document.remove(FORMAT_KEY).is_some();
+        let raw = document.remove(RAW_KEY);
+
+        let mut kv = HashMap::new();
+        for (key, value) in document {
+            let value = match value {
+                // The old double-encoded format
+                serde_json::Value::String(encoded) if !inline => encoded,
+                value => serde_json::to_string(&value)?,
+            };
+            kv.insert(key, value);
+        }
+        if let Some(serde_json::Value::Object(raw)) = raw {
+            for (key, value) in raw {
+                if let serde_json::Value::String(value) = value {
+                    kv.insert(key, value);
+                }
+            }
+        }
+        Ok(kv)
+    }
+
+    fn write(
+        self,
+        kv: &HashMap<String, String>,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let mut document = Document::new();
+        let mut raw = serde_json::Map::new();
+        for (key, value) in kv {
+            match serde_json::from_str(value).ok().and_then(|value| self.inline(value)) {
+                Some(value) => {
+                    document.insert(key.clone(), value);
+                }
+                None => {
+                    raw.insert(key.clone(), serde_json::Value::String(value.clone()));
+                }
+            }
+        }
+        document.insert(FORMAT_KEY.to_owned(), FORMAT_INLINE.into());
+        if !raw.is_empty() {
+            document.insert(RAW_KEY.to_owned(), raw.into());
+        }
+
+        Ok(match self {
+            Format::Json => serde_json::to_string_pretty(&document)?,
+            Format::Ron => ron::ser::to_string_pretty(&document, Default::default())?,
+            Format::Toml => toml::to_string_pretty(&toml::Value::try_from(&document)?)?,
+        })
+    }
+
+    /// `value` as it is written inline, or `None` if it has to be kept as a JSON string.
+    fn inline(self, mut value: serde_json::Value) -> Option<serde_json::Value> {
+        match self {
+            Format::Json | Format::Ron => Some(value),
+            Format::Toml => {
+                remove_nulls(&mut value);
+                if value.is_null() {
+                    return None;
                 }
+                // TOML documents are tables, so try the value as one.
+                let table: Document = std::iter::once((String::new(), value)).collect();
+                let toml = toml::Value::try_from(&table).ok()?;
+                toml::to_string(&toml).ok()?;
+                table.into_iter().next().map(|(_, value)| value)
             }
         }
-        Err(_err) => {
-            // File probably doesn't exist. That's fine.
-            None
+    }
+}
+
+fn remove_nulls(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(object) => {
+            object.retain(|_, value| !value.is_null());
+            object.values_mut().for_each(remove_nulls);
         }
+        serde_json::Value::Array(array) => array.iter_mut().for_each(remove_nulls),
+        _ => {}
     }
 }
+
+/// Read a `FileStorage` file in the format given by its extension.
+/// Only for loading at startup: a corrupt file is moved aside.
+fn read_kv(path: &Path) -> Result<Option<HashMap<String, String>>, StorageError> {
+    read_file(path, |text| Format::from_path(path).parse(text))
+}
+
+/// Like [`read_kv`], but leaves a corrupt file where it is.
+fn parse_kv(path: &Path) -> Result<Option<HashMap<String, String>>, StorageError> {
+    parse_file(path, |text| Format::from_path(path).parse(text))
+}
+
 // ----------------------------------------------------------------------------
 
+/// Write a file by writing `write` to a temporary file in the same directory,
+/// syncing it and renaming it over `path`.
+///
+/// If `path` already exists it is first copied to `path` + `.bak`.
+pub fn write_atomically(
+    path: impl AsRef<Path>,
+    write: impl FnOnce(&mut io::BufWriter<std::fs::File>) -> io::Result<()>,
+) -> io::Result<()> {
+    let path = path.as_ref();
+    let tmp_path = with_added_extension(path, "tmp");
+
+    let result = (|| {
+        let mut writer = io::BufWriter::new(std::fs::File::create(&tmp_path)?);
+        write(&mut writer)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()
+    })();
+    if let Err(err) = result {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(err);
+    }
+
+    if path.exists() {
+        std::fs::copy(path, with_added_extension(path, "bak"))?;
+    }
+    std::fs::rename(&tmp_path, path)
+}
+
+/// `settings.json` -> `settings.json.bak`
+fn with_added_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_owned();
+    file_name.push(".");
+    file_name.push(extension);
+    path.with_file_name(file_name)
+}
+
 /// Alternative to `FileStorage`
-pub fn read_memory(ctx: &egui::Context, memory_json_path: impl AsRef<std::path::Path>) {
-    let memory: Option<egui::Memory> = read_json(memory_json_path);
+pub fn read_memory(
+    ctx: &egui::Context,
+    memory_json_path: impl AsRef<std::path::Path>,
+) -> Result<(), StorageError> {
+    let memory: Option<egui::Memory> = read_json(memory_json_path)?;
     if let Some(memory) = memory {
         *ctx.memory() = memory;
     }
+    Ok(())
 }
 
 /// Alternative to `FileStorage`
@@ -74,35 +791,77 @@ pub fn write_memory(
     ctx: &egui::Context,
     memory_json_path: impl AsRef<std::path::Path>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    serde_json::to_writer_pretty(std::fs::File::create(memory_json_path)?, &*ctx.memory())?;
+    let memory = ctx.memory();
+    write_atomically(memory_json_path, |writer| {
+        serde_json::to_writer_pretty(writer, &*memory)?;
+        Ok(())
+    })?;
     Ok(())
 }
 
 // ----------------------------------------------------------------------------
 
-#[derive(Default, serde::Deserialize, serde::Serialize)]
+#[derive(Clone, Default, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
 pub struct WindowSettings {
     /// outer position of window in physical pixels
     pos: Option<egui::Pos2>,
     /// inner size of window in physical pixels
     size: Option<egui::Vec2>,
+    /// The scale factor `size` was measured in, so it can be converted
+    /// when the window opens on a monitor with a different DPI.
+    scale_factor: Option<f64>,
+    /// Name of the monitor the window was on.
+    /// A window whose position is no longer visible is brought back there, if it still exists.
+    monitor: Option<String>,
+    maximized: bool,
+    fullscreen: bool,
 }
 
 impl WindowSettings {
     pub fn from_json_file(
         settings_json_path: impl AsRef<std::path::Path>,
-    ) -> Option<WindowSettings> {
+    ) -> Result<Option<WindowSettings>, StorageError> {
         read_json(settings_json_path)
     }
 
     pub fn from_display(window: &winit::window::Window) -> Self {
-        Self {
-            pos: window.outer_position().ok().map(|p| egui::pos2(p.x as f32, p.y as f32)),
+        let mut settings = Self::default();
+        settings.update_from_display(window);
+        settings
+    }
 
-            size: Some(egui::vec2(
+    /// Remember the current state of the window.
+    ///
+    /// While the window is maximized or fullscreen, the position and size of the
+    /// normal window are kept, so that is what it goes back to after a restart.
+    /// Call this whenever the window moves or is resized, so the normal geometry is
+    /// known from before it was maximized.
+    pub fn update_from_display(&mut self, window: &winit::window::Window) {
+        let monitor = window.current_monitor();
+        self.fullscreen = window.fullscreen().is_some();
+        self.monitor = monitor.as_ref().and_then(|monitor| monitor.name());
+        // winit 0.23 can't tell whether a window is maximized, so guess from its size.
+        self.maximized = match (monitor, window.outer_position()) {
+            (Some(monitor), Ok(pos)) => {
+                let size = window.outer_size();
+                looks_maximized(
+                    egui::Rect::from_min_size(
+                        egui::pos2(pos.x as f32, pos.y as f32),
+                        egui::vec2(size.width as f32, size.height as f32),
+                    ),
+                    monitor_rect(&monitor),
+                )
+            }
+            _ => false,
+        };
+        if !self.maximized && !self.fullscreen {
+            self.pos = window.outer_position().ok().map(|p| egui::pos2(p.x as f32, p.y as f32));
+            self.size = Some(egui::vec2(
                 window.inner_size().width as f32,
                 window.inner_size().height as f32,
-            )),
+            ));
+            self.scale_factor = Some(window.scale_factor());
         }
     }
 
@@ -110,14 +869,19 @@ impl WindowSettings {
         &self,
         window: winit::window::WindowBuilder,
     ) -> winit::window::WindowBuilder {
-        if let Some(size) = self.size {
-            window.with_inner_size(winit::dpi::PhysicalSize {
+        let window = match (self.size, self.scale_factor) {
+            // Let winit convert the logical size to the scale factor of the monitor it opens on.
+            (Some(size), Some(scale_factor)) => window.with_inner_size(
+                winit::dpi::PhysicalSize { width: size.x as f64, height: size.y as f64 }
+                    .to_logical::<f64>(scale_factor),
+            ),
+            (Some(size), None) => window.with_inner_size(winit::dpi::PhysicalSize {
                 width: size.x as f64,
                 height: size.y as f64,
-            })
-        } else {
-            window
-        }
+            }),
+            (None, _) => window,
+        };
+        window.with_maximized(self.maximized)
 
         // Not yet available in winit: https://github.com/rust-windowing/winit/issues/1190
         // if let Some(pos) = self.pos {
@@ -140,8 +904,365 @@ impl WindowSettings {
         //     });
 
         if let Some(pos) = self.pos {
+            // The monitor it was on may be gone, e.g. after undocking a laptop.
+            let size = window.outer_size();
+            let window_rect = egui::Rect::from_min_max(
+                pos,
+                pos + egui::vec2(size.width as f32, size.height as f32),
+            );
+            let primary_monitor = window.primary_monitor();
+            let mut monitors: Vec<_> = window.available_monitors().collect();
+            // Windows that are completely lost go to the first monitor: the one the window
+            // was on if it is still there, the primary one otherwise.
+            monitors.sort_by_key(|monitor| {
+                let was_on = monitor.name().is_some() && monitor.name() == self.monitor;
+                (!was_on, Some(monitor) != primary_monitor.as_ref())
+            });
+            let monitor_rects: Vec<_> = monitors.iter().map(monitor_rect).collect();
+            let pos = visible_position(window_rect, &monitor_rects);
+
             window
                 .set_outer_position(winit::dpi::PhysicalPosition::new(pos.x as f64, pos.y as f64));
         }
+
+        if self.fullscreen {
+            let monitor = window
+                .available_monitors()
+                .find(|monitor| monitor.name().is_some() && monitor.name() == self.monitor)
+                .or_else(|| window.current_monitor());
+            window.set_fullscreen(Some(winit::window::Fullscreen::Borderless(monitor)));
+        }
+    }
+}
+
+/// The area of `monitor` on the desktop, in physical pixels.
+fn monitor_rect(monitor: &winit::monitor::MonitorHandle) -> egui::Rect {
+    let (pos, size) = (monitor.position(), monitor.size());
+    egui::Rect::from_min_size(
+        egui::pos2(pos.x as f32, pos.y as f32),
+        egui::vec2(size.width as f32, size.height as f32),
+    )
+}
+
+/// Whether a window covering `window` (outer rect, physical pixels) is maximized on `monitor`.
+///
+/// A maximized window fills the monitor except for task bars and docks,
+/// and on Windows its invisible resize borders stick out a few pixels.
+pub fn looks_maximized(window: egui::Rect, monitor: egui::Rect) -> bool {
+    const BORDER: f32 = 16.0;
+    // Anything smaller leaves room for more than a task bar or dock.
+    const MIN_COVERAGE: f32 = 0.85;
+
+    let fits = window.min.x >= monitor.min.x - BORDER
+        && window.min.y >= monitor.min.y - BORDER
+        && window.max.x <= monitor.max.x + BORDER
+        && window.max.y <= monitor.max.y + BORDER;
+    let (window_size, monitor_size) = (window.max - window.min, monitor.max - monitor.min);
+    let covers = |window: f32, monitor: f32| window >= monitor * MIN_COVERAGE;
+    fits && covers(window_size.x, monitor_size.x)
+        && covers(window_size.y, monitor_size.y)
+        // A task bar only takes away from one side.
+        && (window_size.x >= monitor_size.x - BORDER || window_size.y >= monitor_size.y - BORDER)
+}
+
+/// Where to put a window so it is visible on one of the `monitors`, all in physical pixels.
+///
+/// A window that is partly off-screen is moved onto the monitor it overlaps most.
+/// A window that isn't on any monitor is centered on the first one.
+/// If the window is larger than the monitor its top left corner is kept visible,
+/// as that is where the title bar usually is.
+pub fn visible_position(window: egui::Rect, monitors: &[egui::Rect]) -> egui::Pos2 {
+    let overlap = |monitor: &egui::Rect| {
+        let width = window.max.x.min(monitor.max.x) - window.min.x.max(monitor.min.x);
+        let height = window.max.y.min(monitor.max.y) - window.min.y.max(monitor.min.y);
+        width.max(0.0) * height.max(0.0)
+    };
+    let size = window.max - window.min;
+
+    let best = monitors
+        .iter()
+        .max_by(|a, b| overlap(a).partial_cmp(&overlap(b)).unwrap_or(std::cmp::Ordering::Equal));
+    match best {
+        None => window.min,
+        Some(monitor) if overlap(monitor) > 0.0 => clamp_to_monitor(window.min, size, monitor),
+        Some(_) => {
+            let monitor = &monitors[0];
+            let centered = monitor.min + ((monitor.max - monitor.min) - size) / 2.0;
+            clamp_to_monitor(centered, size, monitor)
+        }
+    }
+}
+
+fn clamp_to_monitor(pos: egui::Pos2, size: egui::Vec2, monitor: &egui::Rect) -> egui::Pos2 {
+    egui::pos2(
+        pos.x.min(monitor.max.x - size.x).max(monitor.min.x),
+        pos.y.min(monitor.max.y - size.y).max(monitor.min.y),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: f32, y: f32, width: f32, height: f32) -> egui::Rect {
+        egui::Rect::from_min_size(egui::pos2(x, y), egui::vec2(width, height))
+    }
+
+    /// A fresh, empty directory for a test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("egui_winit_wgpu-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn app_id_resolves_to_config_dir_override() {
+        let dir = test_dir("config");
+        std::env::set_var(CONFIG_DIR_ENV_VAR, &dir);
+        assert_eq!(config_dir(), Some(dir.clone()));
+
+        let mut storage = FileStorage::from_app_id("app").unwrap();
+        assert!(dir.join("app").is_dir());
+        egui::app::Storage::set_string(&mut storage, "key", "1".to_owned());
+        storage.save().unwrap();
+        assert!(dir.join("app").join("settings.json").is_file());
+
+        let storage = FileStorage::from_app_id("app").unwrap();
+        std::env::remove_var(CONFIG_DIR_ENV_VAR);
+        assert_eq!(egui::app::Storage::get_string(&storage, "key"), Some("1"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// The names of the files in `dir` that start with `prefix`, sorted.
+    fn files_starting_with(dir: &Path, prefix: &str) -> Vec<String> {
+        let mut names: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with(prefix))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn corrupt_files_are_moved_aside_without_overwriting() {
+        let dir = test_dir("corrupt");
+        let path = dir.join("settings.json");
+        // Not UTF-8, then not JSON, in all likelihood within the same second.
+        for contents in &[&b"{\"key\": \"\xff\"}"[..], b"{ half written"] {
+            std::fs::write(&path, contents).unwrap();
+            let storage = FileStorage::from_path(&path);
+            match storage.load_error() {
+                Some(StorageError::Corrupt { moved_to: Some(moved_to), .. }) => {
+                    assert_eq!(&std::fs::read(moved_to).unwrap()[..], *contents)
+                }
+                _ => panic!("not moved aside"),
+            }
+            assert!(!path.exists());
+        }
+        assert_eq!(files_starting_with(&dir, "settings.json.corrupt-").len(), 2);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn merge_on_save_keeps_keys_of_other_instances() {
+        let dir = test_dir("merge");
+        let path = dir.join("settings.json");
+        let open = || FileStorage::from_path(&path).with_merge_on_save(true);
+        let (mut first, mut second) = (open(), open());
+        egui::app::Storage::set_string(&mut first, "first", "1".to_owned());
+        egui::app::Storage::set_string(&mut second, "second", "2".to_owned());
+        egui::app::Storage::set_string(&mut second, "shared", "2".to_owned());
+
+        first.save().unwrap();
+        second.save_in_background();
+        // Changed while the background save is running, so that save mustn't undo it.
+        egui::app::Storage::set_string(&mut second, "shared", "3".to_owned());
+        second.save().unwrap();
+
+        let on_disk = open();
+        assert_eq!(egui::app::Storage::get_string(&on_disk, "first"), Some("1"));
+        assert_eq!(egui::app::Storage::get_string(&on_disk, "second"), Some("2"));
+        assert_eq!(egui::app::Storage::get_string(&on_disk, "shared"), Some("3"));
+        // The second instance picked up what the first one saved.
+        assert_eq!(egui::app::Storage::get_string(&second, "first"), Some("1"));
+
+        // Saving again only writes what the first instance changed since.
+        egui::app::Storage::set_string(&mut first, "first", "4".to_owned());
+        first.save().unwrap();
+        let on_disk = open();
+        assert_eq!(egui::app::Storage::get_string(&on_disk, "first"), Some("4"));
+        assert_eq!(egui::app::Storage::get_string(&on_disk, "shared"), Some("3"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn reload_skips_check_while_locked() {
+        let dir = test_dir("locked");
+        let path = dir.join("settings.json");
+        let mut storage = FileStorage::from_path(&path)
+            .with_watch(Duration::from_secs(0), ConflictPolicy::KeepLocal);
+        std::fs::write(&path, r#"{"key": "1"}"#).unwrap();
+
+        let lock = FileLock::acquire(&path).unwrap();
+        assert!(storage.reload_external_changes().is_empty());
+        drop(lock);
+        assert_eq!(storage.reload_external_changes(), ["key"]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn reload_sees_external_changes_after_background_save() {
+        let dir = test_dir("autosave-watch");
+        let path = dir.join("settings.json");
+        let mut storage = FileStorage::from_path(&path)
+            .with_autosave_interval(Duration::from_secs(0))
+            .with_watch(Duration::from_secs(0), ConflictPolicy::KeepLocal);
+
+        egui::app::Storage::set_string(&mut storage, "own", "1".to_owned());
+        storage.save_in_background();
+        for _ in 0..500 {
+            assert!(storage.reload_external_changes().is_empty(), "our own save isn't external");
+            if storage.background_save.is_none() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(storage.background_save.is_none(), "the watch should pick up the save result");
+        assert!(storage.reload_external_changes().is_empty());
+
+        std::fs::write(&path, r#"{"own": "1", "external": "2"}"#).unwrap();
+        assert_eq!(storage.reload_external_changes(), ["external"]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn reload_leaves_corrupt_file_and_retries() {
+        let dir = test_dir("reload");
+        let path = dir.join("settings.json");
+        let mut storage = FileStorage::from_path(&path)
+            .with_watch(Duration::from_secs(0), ConflictPolicy::KeepLocal);
+
+        std::fs::write(&path, "{ half written").unwrap();
+        assert!(storage.reload_external_changes().is_empty());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1 + 1, "only the file and its lock");
+
+        std::fs::write(&path, r#"{"key": "\"value\""}"#).unwrap();
+        assert_eq!(storage.reload_external_changes(), ["key"]);
+        assert_eq!(egui::app::Storage::get_string(&storage, "key"), Some(r#""value""#));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    fn round_trip(format: Format, kv: &HashMap<String, String>) -> HashMap<String, String> {
+        let text = format.write(kv).unwrap();
+        format.parse(&text).unwrap_or_else(|err| panic!("{}\n{}", err, text))
+    }
+
+    fn test_kv(values: &[(&str, serde_json::Value)]) -> HashMap<String, String> {
+        let mut kv: HashMap<String, String> = values
+            .iter()
+            .map(|(key, value)| (key.to_string(), serde_json::to_string(value).unwrap()))
+            .collect();
+        kv.insert("not json".to_owned(), "{ quarantined".to_owned());
+        kv
+    }
+
+    #[test]
+    fn formats_round_trip() {
+        let kv = test_kv(&[
+            ("app", serde_json::json!({"zoom": 1.5, "name": "egui", "list": [1, 2, 3]})),
+            ("null", serde_json::Value::Null),
+            ("nulls", serde_json::json!([1, null, 3])),
+            ("big", serde_json::json!(u64::MAX)),
+            ("string", serde_json::json!("text")),
+        ]);
+        assert_eq!(round_trip(Format::Json, &kv), kv);
+        assert_eq!(round_trip(Format::Ron, &kv), kv);
+        assert_eq!(round_trip(Format::Toml, &kv), kv);
+    }
+
+    #[test]
+    fn toml_leaves_out_none_fields() {
+        let kv = test_kv(&[("app", serde_json::json!({"zoom": 1.5, "name": null}))]);
+        let expected = test_kv(&[("app", serde_json::json!({"zoom": 1.5}))]);
+        assert_eq!(round_trip(Format::Toml, &kv), expected);
+        assert!(Format::Toml.write(&kv).unwrap().contains("[app]\nzoom = 1.5"));
+    }
+
+    #[test]
+    fn loads_double_encoded_files() {
+        let dir = test_dir("legacy");
+        let path = dir.join("settings.json");
+        std::fs::write(&path, r#"{"app": "{\"zoom\":1.5}", "window": "null"}"#).unwrap();
+        let storage = FileStorage::from_path(&path);
+        assert!(storage.load_error().is_none());
+        assert_eq!(egui::app::Storage::get_string(&storage, "app"), Some(r#"{"zoom":1.5}"#));
+        assert_eq!(egui::app::Storage::get_string(&storage, "window"), Some("null"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    fn monitors() -> [egui::Rect; 2] {
+        [rect(0.0, 0.0, 1920.0, 1080.0), rect(1920.0, 0.0, 1280.0, 1024.0)]
+    }
+
+    #[test]
+    fn on_screen_window_stays() {
+        let window = rect(100.0, 100.0, 800.0, 600.0);
+        assert_eq!(visible_position(window, &monitors()), window.min);
+        let window = rect(2000.0, 100.0, 800.0, 600.0);
+        assert_eq!(visible_position(window, &monitors()), window.min);
+    }
+
+    #[test]
+    fn partly_off_screen_window_is_clamped() {
+        // Hanging off the bottom right of the second monitor.
+        let window = rect(2800.0, 800.0, 800.0, 600.0);
+        assert_eq!(visible_position(window, &monitors()), egui::pos2(2400.0, 424.0));
+        // Hanging off the top left of the first.
+        let window = rect(-100.0, -50.0, 800.0, 600.0);
+        assert_eq!(visible_position(window, &monitors()), egui::pos2(0.0, 0.0));
+    }
+
+    #[test]
+    fn window_on_missing_monitor_is_centered_on_the_first() {
+        let window = rect(5000.0, 100.0, 800.0, 600.0);
+        assert_eq!(visible_position(window, &monitors()), egui::pos2(560.0, 240.0));
+    }
+
+    #[test]
+    fn window_larger_than_monitor_keeps_top_left_on_screen() {
+        let window = rect(100.0, 100.0, 2500.0, 1500.0);
+        assert_eq!(visible_position(window, &monitors()[..1]), egui::pos2(0.0, 0.0));
+    }
+
+    #[test]
+    fn no_monitors_leaves_window() {
+        let window = rect(5000.0, 100.0, 800.0, 600.0);
+        assert_eq!(visible_position(window, &[]), window.min);
+    }
+
+    #[test]
+    fn maximized_above_task_bar() {
+        let monitor = rect(0.0, 0.0, 1920.0, 1080.0);
+        assert!(looks_maximized(rect(0.0, 0.0, 1920.0, 1040.0), monitor));
+        // Windows: invisible resize borders outside the monitor.
+        assert!(looks_maximized(rect(-8.0, -8.0, 1936.0, 1056.0), monitor));
+        // Second monitor, task bar on the left.
+        assert!(looks_maximized(
+            rect(1980.0, 0.0, 1860.0, 1080.0),
+            rect(1920.0, 0.0, 1920.0, 1080.0)
+        ));
+    }
+
+    #[test]
+    fn normal_windows_are_not_maximized() {
+        let monitor = rect(0.0, 0.0, 1920.0, 1080.0);
+        assert!(!looks_maximized(rect(100.0, 100.0, 800.0, 600.0), monitor));
+        // Big, but with room on two sides.
+        assert!(!looks_maximized(rect(40.0, 40.0, 1840.0, 1000.0), monitor));
+        // Maximized on another monitor.
+        assert!(!looks_maximized(rect(1920.0, 0.0, 1920.0, 1040.0), monitor));
     }
 }